@@ -30,3 +30,75 @@ cfg_if::cfg_if! {
         pub use self::other::*;
     }
 }
+
+use core::time::Duration;
+
+// Anything older than this most likely isn't measured against the same clock,
+// so we rather don't backdate the event at all.
+const MAX_LATENCY: Duration = Duration::from_secs(1);
+
+// Not every backend has timestamps for its events.
+#[allow(dead_code)]
+fn plausible_latency(latency: Duration) -> Duration {
+    if latency > MAX_LATENCY {
+        Duration::default()
+    } else {
+        latency
+    }
+}
+
+/// The error type shared by all the platform specific hotkey hooks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, snafu::Snafu)]
+pub enum Error {
+    /// The hotkey is already registered.
+    AlreadyRegistered,
+    /// The hotkey is not registered.
+    NotRegistered,
+    /// The active backend has no way to listen for the key. Only the Linux
+    /// backends report this, for keys that aren't on the current keyboard
+    /// mapping. The Windows, macOS and web backends accept every key, even
+    /// if it can never be pressed.
+    #[snafu(display("The key {:?} is not supported by the backend.", code))]
+    KeyNotSupported {
+        /// The key that was attempted to be registered.
+        code: KeyCode,
+    },
+    /// The operating system denied access to the keyboard input.
+    PermissionDenied,
+    /// The hook couldn't be set up on this system.
+    #[snafu(display("The hotkey backend is unavailable: {}", reason))]
+    BackendUnavailable {
+        /// The reason the backend is unavailable.
+        reason: &'static str,
+    },
+    /// The thread processing the keyboard events stopped unexpectedly.
+    ThreadStopped,
+}
+
+/// The result type for the hotkey hooks.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The backend that a hook uses to listen for the hotkeys.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// A low-level keyboard hook on Windows.
+    Windows,
+    /// A Quartz event tap on macOS.
+    MacOS,
+    /// Key grabs on an X11 server. This is used on Linux outside of Wayland
+    /// sessions. In Wayland sessions it's the fallback through XWayland when no
+    /// keyboard can be read directly, but then the keys are only seen while an
+    /// X client is focused.
+    X11,
+    /// Reading the Linux input devices directly. This is used on Linux in
+    /// Wayland sessions if at least one keyboard can be read, and when there
+    /// is no X server at all. It requires read access to `/dev/input`, which
+    /// usually means being in the `input` group.
+    Evdev,
+    /// Keyboard and gamepad events in a web browser.
+    Web,
+    /// Events forwarded by the JavaScript host of a WebAssembly module.
+    WasmHost,
+    /// Hotkeys are not supported on this platform and get ignored.
+    Unsupported,
+}
@@ -0,0 +1,541 @@
+use super::{monotonic_now, Hook, KeyCode, Message, EPOLL_FAILED, PING_TOKEN};
+use crate::{plausible_latency, Backend, Error, Result};
+use mio::{unix::SourceFd, Events, Interest, Poll, Registry, Token, Waker};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    slice,
+    sync::mpsc::channel,
+    thread,
+    time::Duration,
+};
+
+const INPUT_DIR: &str = "/dev/input";
+
+const EV_KEY: u16 = 0x01;
+const KEY_RELEASED: i32 = 0;
+const KEY_PRESSED: i32 = 1;
+// _IOR('E', 0x20, u32) from `linux/input.h`, which is EVIOCGBIT(0, 4).
+const EVIOCGBIT_EV: u32 = 0x80044520;
+// _IOW('E', 0xa0, int) from `linux/input.h`.
+const EVIOCSCLOCKID: u32 = 0x400445a0;
+
+const INOTIFY_TOKEN: Token = Token(1);
+
+const NO_INPUT_DEVICES: Error = Error::BackendUnavailable {
+    reason: "Couldn't access the input devices.",
+};
+
+pub const NO_KEYBOARDS: Error = Error::BackendUnavailable {
+    reason: "There is no keyboard to read.",
+};
+
+fn device_token(index: usize) -> Token {
+    Token(index + 2)
+}
+
+struct Device {
+    name: OsString,
+    file: File,
+    pressed_keys: HashSet<u16>,
+}
+
+fn watch_input_dir() -> io::Result<File> {
+    unsafe {
+        let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = File::from_raw_fd(fd);
+
+        // Device nodes get created with restrictive permissions first and
+        // only afterwards get handed to the `input` group, so we need to
+        // retry opening them when their attributes change.
+        let path = b"/dev/input\0";
+        if libc::inotify_add_watch(fd, path.as_ptr().cast(), libc::IN_CREATE | libc::IN_ATTRIB) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(inotify)
+    }
+}
+
+fn has_key_events(event_types: u64) -> bool {
+    event_types & (1 << EV_KEY) != 0
+}
+
+fn parse_sysfs_event_types(bits: &str) -> Option<u64> {
+    // The bit mask is split into words with the lowest bits coming last.
+    let lowest_bits = bits.split_whitespace().last()?;
+    u64::from_str_radix(lowest_bits, 16).ok()
+}
+
+fn reports_key_events(device: &File) -> bool {
+    let mut event_types: u32 = 0;
+    let result = unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGBIT_EV as _, &mut event_types) };
+    // The request returns the amount of bytes copied. Anything that doesn't
+    // understand it isn't an input device.
+    result >= 0 && has_key_events(event_types as u64)
+}
+
+// Devices we aren't allowed to open can still be queried through sysfs.
+fn sysfs_reports_key_events(name: &OsStr) -> bool {
+    let mut path = OsString::from("/sys/class/input/");
+    path.push(name);
+    path.push("/device/capabilities/ev");
+
+    let event_types = fs::read_to_string(path)
+        .ok()
+        .and_then(|bits| parse_sysfs_event_types(&bits))
+        .unwrap_or(!0);
+
+    has_key_events(event_types)
+}
+
+fn use_monotonic_clock(device: &File) {
+    // The event timestamps use the realtime clock by default, which may jump,
+    // so we switch them to the monotonic clock.
+    let clock_id: libc::c_int = libc::CLOCK_MONOTONIC;
+    unsafe {
+        libc::ioctl(device.as_raw_fd(), EVIOCSCLOCKID as _, &clock_id);
+    }
+}
+
+/// Opens all the devices that report key events and aren't open yet. Also
+/// returns whether any such device couldn't be opened due to missing
+/// permissions.
+fn open_new_devices(devices: &[Option<Device>]) -> io::Result<(Vec<Device>, bool)> {
+    let mut new_devices = Vec::new();
+    let mut permission_denied = false;
+
+    for entry in fs::read_dir(INPUT_DIR)?.flatten() {
+        let name = entry.file_name();
+        if !name.as_bytes().starts_with(b"event")
+            || devices.iter().flatten().any(|device| device.name == name)
+        {
+            continue;
+        }
+        match OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(entry.path())
+        {
+            Ok(file) => {
+                if reports_key_events(&file) {
+                    use_monotonic_clock(&file);
+                    new_devices.push(Device {
+                        name,
+                        file,
+                        pressed_keys: HashSet::new(),
+                    });
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                if sysfs_reports_key_events(&name) {
+                    permission_denied = true;
+                }
+            }
+            Err(_) => {}
+        }
+    }
+
+    Ok((new_devices, permission_denied))
+}
+
+fn add_device(
+    registry: &Registry,
+    devices: &mut Vec<Option<Device>>,
+    device: Device,
+) -> io::Result<()> {
+    let index = devices
+        .iter()
+        .position(Option::is_none)
+        .unwrap_or(devices.len());
+
+    registry.register(
+        &mut SourceFd(&device.file.as_raw_fd()),
+        device_token(index),
+        Interest::READABLE,
+    )?;
+
+    if index == devices.len() {
+        devices.push(Some(device));
+    } else {
+        devices[index] = Some(device);
+    }
+
+    Ok(())
+}
+
+fn read_event(mut device: &File) -> io::Result<libc::input_event> {
+    unsafe {
+        let mut event = mem::MaybeUninit::<libc::input_event>::zeroed();
+        let buf = slice::from_raw_parts_mut(
+            event.as_mut_ptr().cast::<u8>(),
+            mem::size_of::<libc::input_event>(),
+        );
+        // The kernel only ever hands out whole events.
+        if device.read(buf)? != buf.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(event.assume_init())
+    }
+}
+
+/// Unless `allow_no_keyboards` is set, this fails with [`NO_KEYBOARDS`] if
+/// there is no keyboard to read yet.
+pub fn new(allow_no_keyboards: bool) -> Result<Hook> {
+    let (sender, receiver) = channel();
+
+    // The directory is watched before it's scanned, so no device plugged in
+    // in the meantime gets missed.
+    let mut inotify = watch_input_dir().map_err(|_| NO_INPUT_DEVICES)?;
+
+    let mut devices = Vec::new();
+    let (new_devices, permission_denied) =
+        open_new_devices(&devices).map_err(|_| NO_INPUT_DEVICES)?;
+
+    // Reading the input devices usually requires the user to be in the
+    // `input` group. Hotkeys that only work on some of the keyboards would be
+    // confusing, so we report this even if we could open some of them.
+    if permission_denied {
+        return Err(Error::PermissionDenied);
+    }
+    if new_devices.is_empty() && !allow_no_keyboards {
+        return Err(NO_KEYBOARDS);
+    }
+
+    let mut poll = Poll::new().map_err(|_| EPOLL_FAILED)?;
+
+    let waker = Waker::new(poll.registry(), PING_TOKEN).map_err(|_| EPOLL_FAILED)?;
+
+    poll.registry()
+        .register(
+            &mut SourceFd(&inotify.as_raw_fd()),
+            INOTIFY_TOKEN,
+            Interest::READABLE,
+        )
+        .map_err(|_| EPOLL_FAILED)?;
+
+    for device in new_devices {
+        add_device(poll.registry(), &mut devices, device).map_err(|_| EPOLL_FAILED)?;
+    }
+
+    let join_handle = thread::spawn(move || -> Result<()> {
+        let mut result = Ok(());
+        let mut events = Events::with_capacity(1024);
+        let mut hotkeys = HashMap::new();
+
+        'event_loop: loop {
+            if poll.poll(&mut events, None).is_err() {
+                result = Err(EPOLL_FAILED);
+                break 'event_loop;
+            }
+
+            for mio_event in &events {
+                if mio_event.token() == PING_TOKEN {
+                    for message in receiver.try_iter() {
+                        match message {
+                            Message::Register(key, callback, promise) => {
+                                if let Some(code) = to_evdev_code(key) {
+                                    if let Entry::Vacant(vacant) = hotkeys.entry(code) {
+                                        vacant.insert((key, callback));
+                                        promise.set(Ok(()));
+                                    } else {
+                                        promise.set(Err(Error::AlreadyRegistered));
+                                    }
+                                } else {
+                                    promise.set(Err(Error::KeyNotSupported { code: key }));
+                                }
+                            }
+                            Message::Unregister(key, promise) => {
+                                let removed = to_evdev_code(key)
+                                    .and_then(|code| hotkeys.remove(&code))
+                                    .is_some();
+
+                                if removed {
+                                    promise.set(Ok(()));
+                                } else {
+                                    promise.set(Err(Error::NotRegistered));
+                                }
+                            }
+                            Message::Rebind(old, new, promise) => {
+                                let old_code =
+                                    to_evdev_code(old).filter(|code| hotkeys.contains_key(code));
+
+                                if let Some(old_code) = old_code {
+                                    if let Some(new_code) = to_evdev_code(new) {
+                                        if old_code != new_code && hotkeys.contains_key(&new_code) {
+                                            promise.set(Err(Error::AlreadyRegistered));
+                                        } else {
+                                            let (_, callback) = hotkeys.remove(&old_code).unwrap();
+                                            hotkeys.insert(new_code, (new, callback));
+                                            promise.set(Ok(()));
+                                        }
+                                    } else {
+                                        promise.set(Err(Error::KeyNotSupported { code: new }));
+                                    }
+                                } else {
+                                    promise.set(Err(Error::NotRegistered));
+                                }
+                            }
+                            Message::IsPressed(key, promise) => {
+                                promise.set(matches!(
+                                    to_evdev_code(key),
+                                    Some(code) if devices
+                                        .iter()
+                                        .flatten()
+                                        .any(|device| device.pressed_keys.contains(&code))
+                                ));
+                            }
+                            Message::Trigger(key, promise) => {
+                                let callback =
+                                    to_evdev_code(key).and_then(|code| hotkeys.get_mut(&code));
+
+                                if let Some((_, callback)) = callback {
+                                    callback(key, Duration::default());
+                                    promise.set(Ok(()));
+                                } else {
+                                    promise.set(Err(Error::NotRegistered));
+                                }
+                            }
+                            Message::End => {
+                                break 'event_loop;
+                            }
+                        }
+                    }
+                } else if mio_event.token() == INOTIFY_TOKEN {
+                    // We don't care about the individual notifications, as
+                    // we simply look for any devices we don't know yet.
+                    let mut buf = [0; 4096];
+                    while matches!(inotify.read(&mut buf), Ok(len) if len > 0) {}
+
+                    // There's no one to report missing permissions to at
+                    // this point, so those devices are skipped.
+                    if let Ok((new_devices, _)) = open_new_devices(&devices) {
+                        for device in new_devices {
+                            add_device(poll.registry(), &mut devices, device).ok();
+                        }
+                    }
+                } else {
+                    let index = mio_event.token().0 - 2;
+                    let slot = &mut devices[index];
+                    if let Some(device) = slot {
+                        loop {
+                            match read_event(&device.file) {
+                                Ok(event) if event.type_ == EV_KEY => {
+                                    // Key repeats have a value of 2, we are
+                                    // only interested in the initial press.
+                                    if event.value == KEY_PRESSED {
+                                        device.pressed_keys.insert(event.code);
+                                        if let Some((key, callback)) = hotkeys.get_mut(&event.code)
+                                        {
+                                            let pressed_at = Duration::new(
+                                                event.time.tv_sec as u64,
+                                                event.time.tv_usec as u32 * 1000,
+                                            );
+                                            let latency = monotonic_now()
+                                                .checked_sub(pressed_at)
+                                                .unwrap_or_default();
+                                            callback(*key, plausible_latency(latency));
+                                        }
+                                    } else if event.value == KEY_RELEASED {
+                                        device.pressed_keys.remove(&event.code);
+                                    }
+                                }
+                                Ok(_) => {}
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                                Err(_) => {
+                                    // The device got unplugged. Dropping it
+                                    // also forgets about the keys that were
+                                    // held on it. It gets picked up again
+                                    // once it's plugged back in.
+                                    poll.registry()
+                                        .deregister(&mut SourceFd(&device.file.as_raw_fd()))
+                                        .ok();
+                                    *slot = None;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        result
+    });
+
+    Ok(Hook {
+        backend: Backend::Evdev,
+        sender,
+        waker,
+        join_handle: Some(join_handle),
+    })
+}
+
+/// Maps the key to the `KEY_*` code from `linux/input-event-codes.h`, assuming
+/// a US layout as the kernel does. This mirrors `XKeysymToKeycode`, so keys
+/// that share a physical key, like `Numpad7` and `NumpadHome`, map to the
+/// same code.
+fn to_evdev_code(key: KeyCode) -> Option<u16> {
+    use self::KeyCode::*;
+    Some(match key {
+        Escape => 1,
+        D1 | Exclam => 2,
+        D2 | At => 3,
+        D3 | NumberSign => 4,
+        D4 | Dollar => 5,
+        D5 | Percent => 6,
+        D6 | AsciiCircum => 7,
+        D7 | Ampersand => 8,
+        D8 | Asterisk => 9,
+        D9 | ParenLeft => 10,
+        D0 | ParenRight => 11,
+        Minus | Underscore => 12,
+        Equal | Plus => 13,
+        BackSpace => 14,
+        Tab => 15,
+        Q | LowercaseQ => 16,
+        W | LowercaseW => 17,
+        E | LowercaseE => 18,
+        R | LowercaseR => 19,
+        T | LowercaseT => 20,
+        Y | LowercaseY => 21,
+        U | LowercaseU => 22,
+        I | LowercaseI => 23,
+        O | LowercaseO => 24,
+        P | LowercaseP => 25,
+        BracketLeft | BraceLeft => 26,
+        BracketRight | BraceRight => 27,
+        Return => 28,
+        ControlL => 29,
+        A | LowercaseA => 30,
+        S | LowercaseS => 31,
+        D | LowercaseD => 32,
+        F | LowercaseF => 33,
+        G | LowercaseG => 34,
+        H | LowercaseH => 35,
+        J | LowercaseJ => 36,
+        K | LowercaseK => 37,
+        L | LowercaseL => 38,
+        Semicolon | Colon => 39,
+        Apostrophe | Quotedbl => 40,
+        Grave | AsciiTilde => 41,
+        ShiftL => 42,
+        Backslash | Bar => 43,
+        Z | LowercaseZ => 44,
+        X | LowercaseX => 45,
+        C | LowercaseC => 46,
+        V | LowercaseV => 47,
+        B | LowercaseB => 48,
+        N | LowercaseN => 49,
+        M | LowercaseM => 50,
+        Comma | Less => 51,
+        Period | Greater => 52,
+        Slash | Question => 53,
+        ShiftR => 54,
+        NumpadMultiply => 55,
+        AltL => 56,
+        Space => 57,
+        CapsLock => 58,
+        F1 => 59,
+        F2 => 60,
+        F3 => 61,
+        F4 => 62,
+        F5 => 63,
+        F6 => 64,
+        F7 => 65,
+        F8 => 66,
+        F9 => 67,
+        F10 => 68,
+        NumLock => 69,
+        ScrollLock => 70,
+        Numpad7 | NumpadHome => 71,
+        Numpad8 | NumpadUp => 72,
+        Numpad9 | NumpadPageUp => 73,
+        NumpadSubtract => 74,
+        Numpad4 | NumpadLeft => 75,
+        Numpad5 | NumpadBegin => 76,
+        Numpad6 | NumpadRight => 77,
+        NumpadAdd => 78,
+        Numpad1 | NumpadEnd => 79,
+        Numpad2 | NumpadDown => 80,
+        Numpad3 | NumpadPageDown => 81,
+        Numpad0 | NumpadInsert => 82,
+        NumpadDecimal | NumpadDelete => 83,
+        ZenkakuHankaku => 85,
+        F11 => 87,
+        F12 => 88,
+        Katakana => 90,
+        Hiragana => 91,
+        Henkan => 92,
+        HiraganaKatakana => 93,
+        Muhenkan => 94,
+        NumpadEnter => 96,
+        ControlR => 97,
+        NumpadDivide => 98,
+        Print | SysReq => 99,
+        AltR => 100,
+        Linefeed => 101,
+        Home => 102,
+        Up => 103,
+        PageUp => 104,
+        Left => 105,
+        Right => 106,
+        End => 107,
+        Down => 108,
+        PageDown => 109,
+        Insert => 110,
+        Delete => 111,
+        NumpadEqual => 117,
+        Pause | Break => 119,
+        NumpadSeparator => 121,
+        SuperL | MetaL => 125,
+        SuperR | MetaR => 126,
+        Menu => 127,
+        Cancel => 128,
+        Redo => 129,
+        Undo => 131,
+        Find => 136,
+        Help => 138,
+        F13 => 183,
+        F14 => 184,
+        F15 => 185,
+        F16 => 186,
+        F17 => 187,
+        F18 => 188,
+        F19 => 189,
+        F20 => 190,
+        F21 => 191,
+        F22 => 192,
+        F23 => 193,
+        F24 => 194,
+        _ => return None,
+    })
+}
+
+#[test]
+fn key_event_capability() {
+    // A keyboard reports synchronization, key, misc, LED and repeat events.
+    assert!(has_key_events(0x120013));
+    // An accelerometer only reports synchronization and absolute axis events.
+    assert!(!has_key_events(0x9));
+}
+
+#[test]
+fn sysfs_event_types() {
+    assert_eq!(parse_sysfs_event_types("120013\n"), Some(0x120013));
+    assert_eq!(parse_sysfs_event_types("1 b\n"), Some(0xb));
+    assert_eq!(parse_sysfs_event_types(""), None);
+    assert_eq!(parse_sysfs_event_types("xyz"), None);
+}
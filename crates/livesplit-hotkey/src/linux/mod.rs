@@ -1,46 +1,50 @@
+mod evdev;
 mod key_code;
+mod x11;
 pub use self::key_code::KeyCode;
 
-use mio::{unix::SourceFd, Events, Interest, Poll, Token, Waker};
+use crate::{Backend, Error, Result};
+use mio::{Token, Waker};
 use promising_future::{future_promise, Promise};
-use std::{
-    collections::hash_map::{Entry, HashMap},
-    mem,
-    os::raw::{c_int, c_uint},
-    ptr,
-    sync::mpsc::{channel, Sender},
-    thread::{self, JoinHandle},
-};
-use x11_dl::xlib::{
-    AnyKey, AnyModifier, Display, GrabModeAsync, KeyPress, XErrorEvent, XKeyEvent, Xlib,
-};
-
-#[derive(Debug, Copy, Clone, snafu::Snafu)]
-pub enum Error {
-    NoXLib,
-    OpenXServerConnection,
-    EPoll,
-    ThreadStopped,
-    AlreadyRegistered,
-    NotRegistered,
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
+use std::{env, sync::mpsc::Sender, thread::JoinHandle, time::Duration};
 
 enum Message {
     Register(
         KeyCode,
-        Box<dyn FnMut() + Send + 'static>,
+        Box<dyn FnMut(KeyCode, Duration) + Send + 'static>,
         Promise<Result<()>>,
     ),
     Unregister(KeyCode, Promise<Result<()>>),
+    Rebind(KeyCode, KeyCode, Promise<Result<()>>),
+    Trigger(KeyCode, Promise<Result<()>>),
+    IsPressed(KeyCode, Promise<bool>),
     End,
 }
 
-const X_TOKEN: Token = Token(0);
-const PING_TOKEN: Token = Token(1);
+const PING_TOKEN: Token = Token(0);
+
+const EPOLL_FAILED: Error = Error::BackendUnavailable {
+    reason: "Couldn't poll for events.",
+};
+
+fn monotonic_now() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now);
+    }
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+fn is_wayland_session() -> bool {
+    env::var_os("WAYLAND_DISPLAY").is_some()
+        || matches!(env::var("XDG_SESSION_TYPE").as_deref(), Ok("wayland"))
+}
 
 pub struct Hook {
+    backend: Backend,
     sender: Sender<Message>,
     waker: Waker,
     join_handle: Option<JoinHandle<Result<()>>>,
@@ -56,159 +60,80 @@ impl Drop for Hook {
     }
 }
 
-unsafe fn ungrab_all(xlib: &Xlib, display: *mut Display) {
-    let screencount = (xlib.XScreenCount)(display);
-    for screen in 0..screencount {
-        let rootwindow = (xlib.XRootWindow)(display, screen);
-        for _i in 0..rootwindow {
-            // FIXME: This loop looks very stupid, but it somehow it prevents
-            // button presses getting lost.
-            (xlib.XUngrabKey)(display, AnyKey, AnyModifier, rootwindow);
-        }
-    }
-}
-
-unsafe fn grab_all(xlib: &Xlib, display: *mut Display, keylist: Vec<c_uint>) {
-    ungrab_all(xlib, display);
-    let screencount = (xlib.XScreenCount)(display);
-    for screen in 0..screencount {
-        let rootwindow = (xlib.XRootWindow)(display, screen);
-        for code in &keylist {
-            (xlib.XGrabKey)(
-                display,
-                *code as _,
-                AnyModifier,
-                rootwindow,
-                false as _,
-                GrabModeAsync,
-                GrabModeAsync,
-            );
-        }
-    }
-}
-
-unsafe extern "C" fn handle_error(_: *mut Display, _: *mut XErrorEvent) -> c_int {
-    0
-}
-
 impl Hook {
     pub fn new() -> Result<Self> {
-        unsafe {
-            let (sender, receiver) = channel();
-
-            let xlib = Xlib::open().map_err(|_| Error::NoXLib)?;
-            (xlib.XSetErrorHandler)(Some(handle_error));
-
-            let display = (xlib.XOpenDisplay)(ptr::null());
-            if display.is_null() {
-                return Err(Error::OpenXServerConnection);
+        // Wayland sessions usually still run XWayland, but its key grabs only
+        // see the keys while an X client is focused. So there we read the
+        // input devices directly if there's any keyboard we can read. Most
+        // users don't have access to them though, so XWayland is still better
+        // than nothing. Everywhere else X11 is preferred, as it doesn't need
+        // any special permissions, with the input devices as the fallback for
+        // headless setups, where keyboards may also get plugged in later.
+        if is_wayland_session() {
+            match evdev::new(false) {
+                Err(evdev_error) => x11::new().or_else(|_| {
+                    if evdev_error == evdev::NO_KEYBOARDS {
+                        evdev::new(true)
+                    } else {
+                        Err(evdev_error)
+                    }
+                }),
+                result => result,
             }
+        } else {
+            match x11::new() {
+                Err(Error::BackendUnavailable { .. }) => evdev::new(true),
+                result => result,
+            }
+        }
+    }
 
-            let fd = (xlib.XConnectionNumber)(display) as std::os::unix::io::RawFd;
-            let mut poll = Poll::new().map_err(|_| Error::EPoll)?;
-
-            let waker = Waker::new(poll.registry(), PING_TOKEN).map_err(|_| Error::EPoll)?;
-
-            poll.registry()
-                .register(
-                    &mut SourceFd(&fd),
-                    X_TOKEN,
-                    Interest::READABLE | Interest::WRITABLE,
-                )
-                .map_err(|_| Error::EPoll)?;
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
 
-            struct XData(Xlib, *mut Display);
-            unsafe impl Send for XData {}
-            let xdata = XData(xlib, display);
+    pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        let (future, promise) = future_promise();
 
-            let join_handle = thread::spawn(move || -> Result<()> {
-                let XData(xlib, display) = xdata;
+        self.sender
+            .send(Message::Register(hotkey, Box::new(callback), promise))
+            .map_err(|_| Error::ThreadStopped)?;
 
-                let mut result = Ok(());
-                let mut events = Events::with_capacity(1024);
-                let mut hotkeys = HashMap::new();
+        self.waker.wake().map_err(|_| Error::ThreadStopped)?;
 
-                'event_loop: loop {
-                    if poll.poll(&mut events, None).is_err() {
-                        result = Err(Error::EPoll);
-                        break 'event_loop;
-                    }
+        future.value().ok_or(Error::ThreadStopped)?
+    }
 
-                    for mio_event in &events {
-                        if mio_event.token() == PING_TOKEN {
-                            for message in receiver.try_iter() {
-                                match message {
-                                    Message::Register(key, callback, promise) => {
-                                        let code =
-                                            (xlib.XKeysymToKeycode)(display, key as _) as c_uint;
-
-                                        if let Entry::Vacant(vacant) = hotkeys.entry(code) {
-                                            vacant.insert(callback);
-                                            promise.set(Ok(()));
-                                        } else {
-                                            promise.set(Err(Error::AlreadyRegistered));
-                                        }
-                                        let keys = hotkeys.keys().copied().collect();
-                                        grab_all(&xlib, display, keys);
-                                    }
-                                    Message::Unregister(key, promise) => {
-                                        let code =
-                                            (xlib.XKeysymToKeycode)(display, key as _) as c_uint;
-
-                                        if hotkeys.remove(&code).is_some() {
-                                            promise.set(Ok(()));
-                                        } else {
-                                            promise.set(Err(Error::NotRegistered));
-                                        }
-                                        let keys = hotkeys.keys().copied().collect();
-                                        grab_all(&xlib, display, keys);
-                                    }
-                                    Message::End => {
-                                        break 'event_loop;
-                                    }
-                                }
-                            }
-                        } else if mio_event.token() == X_TOKEN {
-                            while (xlib.XPending)(display) != 0 {
-                                let mut event = mem::MaybeUninit::uninit();
-                                (xlib.XNextEvent)(display, event.as_mut_ptr());
-                                let event = event.assume_init();
-                                if event.get_type() == KeyPress {
-                                    let event: &XKeyEvent = event.as_ref();
-                                    if let Some(callback) = hotkeys.get_mut(&event.keycode) {
-                                        callback();
-                                    }
-                                    // FIXME: We should check else here: these amount to lost
-                                    // keypresses.
-                                }
-                            }
-                        }
-                    }
-                }
+    pub fn register_consuming<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        // Grabbing a key on X11 already keeps it from reaching the focused
+        // window. Reading the input devices directly can't suppress single
+        // keys, so this is only best-effort there.
+        self.register(hotkey, callback)
+    }
 
-                ungrab_all(&xlib, display);
+    pub fn unregister(&self, hotkey: KeyCode) -> Result<()> {
+        let (future, promise) = future_promise();
 
-                (xlib.XCloseDisplay)(display);
+        self.sender
+            .send(Message::Unregister(hotkey, promise))
+            .map_err(|_| Error::ThreadStopped)?;
 
-                result
-            });
+        self.waker.wake().map_err(|_| Error::ThreadStopped)?;
 
-            Ok(Hook {
-                sender,
-                waker,
-                join_handle: Some(join_handle),
-            })
-        }
+        future.value().ok_or(Error::ThreadStopped)?
     }
 
-    pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
-    where
-        F: FnMut() + Send + 'static,
-    {
+    pub fn rebind(&self, old: KeyCode, new: KeyCode) -> Result<()> {
         let (future, promise) = future_promise();
 
         self.sender
-            .send(Message::Register(hotkey, Box::new(callback), promise))
+            .send(Message::Rebind(old, new, promise))
             .map_err(|_| Error::ThreadStopped)?;
 
         self.waker.wake().map_err(|_| Error::ThreadStopped)?;
@@ -216,11 +141,26 @@ impl Hook {
         future.value().ok_or(Error::ThreadStopped)?
     }
 
-    pub fn unregister(&self, hotkey: KeyCode) -> Result<()> {
+    pub fn is_pressed(&self, hotkey: KeyCode) -> bool {
+        let (future, promise) = future_promise();
+
+        if self
+            .sender
+            .send(Message::IsPressed(hotkey, promise))
+            .is_err()
+            || self.waker.wake().is_err()
+        {
+            return false;
+        }
+
+        future.value().unwrap_or(false)
+    }
+
+    pub fn trigger(&self, hotkey: KeyCode) -> Result<()> {
         let (future, promise) = future_promise();
 
         self.sender
-            .send(Message::Unregister(hotkey, promise))
+            .send(Message::Trigger(hotkey, promise))
             .map_err(|_| Error::ThreadStopped)?;
 
         self.waker.wake().map_err(|_| Error::ThreadStopped)?;
@@ -232,16 +172,30 @@ impl Hook {
 #[test]
 fn test() {
     let hook = Hook::new().unwrap();
-    hook.register(KeyCode::Numpad1, || println!("A")).unwrap();
+    hook.register(KeyCode::Numpad1, |_, _| println!("A"))
+        .unwrap();
     println!("Press Numpad1");
-    thread::sleep(std::time::Duration::from_secs(5));
+    std::thread::sleep(std::time::Duration::from_secs(5));
     hook.unregister(KeyCode::Numpad1).unwrap();
-    hook.register(KeyCode::Numpad4, || println!("B")).unwrap();
+    hook.register(KeyCode::Numpad4, |_, _| println!("B"))
+        .unwrap();
     println!("Press Numpad4");
-    thread::sleep(std::time::Duration::from_secs(5));
+    std::thread::sleep(std::time::Duration::from_secs(5));
     hook.unregister(KeyCode::Numpad4).unwrap();
-    hook.register(KeyCode::Numpad1, || println!("C")).unwrap();
+    hook.register(KeyCode::Numpad1, |_, _| println!("C"))
+        .unwrap();
     println!("Press Numpad1");
-    thread::sleep(std::time::Duration::from_secs(5));
+    std::thread::sleep(std::time::Duration::from_secs(5));
     hook.unregister(KeyCode::Numpad1).unwrap();
 }
+
+#[test]
+fn trigger() {
+    let hook = Hook::new().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    hook.register(KeyCode::Numpad1, move |key, _| sender.send(key).unwrap())
+        .unwrap();
+    hook.trigger(KeyCode::Numpad1).unwrap();
+    assert_eq!(receiver.try_recv(), Ok(KeyCode::Numpad1));
+    assert_eq!(hook.trigger(KeyCode::Numpad2), Err(Error::NotRegistered));
+}
@@ -0,0 +1,224 @@
+use super::{monotonic_now, Hook, Message, EPOLL_FAILED, PING_TOKEN};
+use crate::{plausible_latency, Backend, Error, Result};
+use mio::{unix::SourceFd, Events, Interest, Poll, Token, Waker};
+use std::{
+    collections::hash_map::{Entry, HashMap},
+    mem,
+    os::raw::{c_int, c_uint},
+    ptr,
+    sync::mpsc::channel,
+    thread,
+    time::Duration,
+};
+use x11_dl::xlib::{
+    AnyKey, AnyModifier, Display, GrabModeAsync, KeyPress, XErrorEvent, XKeyEvent, Xlib,
+};
+
+const X_TOKEN: Token = Token(1);
+
+unsafe fn ungrab_all(xlib: &Xlib, display: *mut Display) {
+    let screencount = (xlib.XScreenCount)(display);
+    for screen in 0..screencount {
+        let rootwindow = (xlib.XRootWindow)(display, screen);
+        for _i in 0..rootwindow {
+            // FIXME: This loop looks very stupid, but it somehow it prevents
+            // button presses getting lost.
+            (xlib.XUngrabKey)(display, AnyKey, AnyModifier, rootwindow);
+        }
+    }
+}
+
+unsafe fn grab_all(xlib: &Xlib, display: *mut Display, keylist: Vec<c_uint>) {
+    ungrab_all(xlib, display);
+    let screencount = (xlib.XScreenCount)(display);
+    for screen in 0..screencount {
+        let rootwindow = (xlib.XRootWindow)(display, screen);
+        for code in &keylist {
+            (xlib.XGrabKey)(
+                display,
+                *code as _,
+                AnyModifier,
+                rootwindow,
+                false as _,
+                GrabModeAsync,
+                GrabModeAsync,
+            );
+        }
+    }
+}
+
+unsafe extern "C" fn handle_error(_: *mut Display, _: *mut XErrorEvent) -> c_int {
+    0
+}
+
+pub fn new() -> Result<Hook> {
+    unsafe {
+        let (sender, receiver) = channel();
+
+        let xlib = Xlib::open().map_err(|_| Error::BackendUnavailable {
+            reason: "Couldn't load Xlib.",
+        })?;
+        (xlib.XSetErrorHandler)(Some(handle_error));
+
+        let display = (xlib.XOpenDisplay)(ptr::null());
+        if display.is_null() {
+            return Err(Error::BackendUnavailable {
+                reason: "Couldn't connect to the X server.",
+            });
+        }
+
+        let fd = (xlib.XConnectionNumber)(display) as std::os::unix::io::RawFd;
+        let mut poll = Poll::new().map_err(|_| EPOLL_FAILED)?;
+
+        let waker = Waker::new(poll.registry(), PING_TOKEN).map_err(|_| EPOLL_FAILED)?;
+
+        poll.registry()
+            .register(
+                &mut SourceFd(&fd),
+                X_TOKEN,
+                Interest::READABLE | Interest::WRITABLE,
+            )
+            .map_err(|_| EPOLL_FAILED)?;
+
+        struct XData(Xlib, *mut Display);
+        unsafe impl Send for XData {}
+        let xdata = XData(xlib, display);
+
+        let join_handle = thread::spawn(move || -> Result<()> {
+            let XData(xlib, display) = xdata;
+
+            let mut result = Ok(());
+            let mut events = Events::with_capacity(1024);
+            let mut hotkeys = HashMap::new();
+
+            'event_loop: loop {
+                if poll.poll(&mut events, None).is_err() {
+                    result = Err(EPOLL_FAILED);
+                    break 'event_loop;
+                }
+
+                for mio_event in &events {
+                    if mio_event.token() == PING_TOKEN {
+                        for message in receiver.try_iter() {
+                            match message {
+                                Message::Register(key, callback, promise) => {
+                                    let code = (xlib.XKeysymToKeycode)(display, key as _) as c_uint;
+
+                                    if code == 0 {
+                                        // The keysym isn't on the current
+                                        // keyboard mapping.
+                                        promise.set(Err(Error::KeyNotSupported { code: key }));
+                                        continue;
+                                    }
+
+                                    if let Entry::Vacant(vacant) = hotkeys.entry(code) {
+                                        vacant.insert((key, callback));
+                                        promise.set(Ok(()));
+                                    } else {
+                                        promise.set(Err(Error::AlreadyRegistered));
+                                    }
+                                    let keys = hotkeys.keys().copied().collect();
+                                    grab_all(&xlib, display, keys);
+                                }
+                                Message::Unregister(key, promise) => {
+                                    let code = (xlib.XKeysymToKeycode)(display, key as _) as c_uint;
+
+                                    if hotkeys.remove(&code).is_some() {
+                                        promise.set(Ok(()));
+                                    } else {
+                                        promise.set(Err(Error::NotRegistered));
+                                    }
+                                    let keys = hotkeys.keys().copied().collect();
+                                    grab_all(&xlib, display, keys);
+                                }
+                                Message::Rebind(old, new, promise) => {
+                                    let old_code =
+                                        (xlib.XKeysymToKeycode)(display, old as _) as c_uint;
+                                    let new_code =
+                                        (xlib.XKeysymToKeycode)(display, new as _) as c_uint;
+
+                                    if !hotkeys.contains_key(&old_code) {
+                                        promise.set(Err(Error::NotRegistered));
+                                    } else if new_code == 0 {
+                                        promise.set(Err(Error::KeyNotSupported { code: new }));
+                                    } else if old_code != new_code
+                                        && hotkeys.contains_key(&new_code)
+                                    {
+                                        promise.set(Err(Error::AlreadyRegistered));
+                                    } else {
+                                        let (_, callback) = hotkeys.remove(&old_code).unwrap();
+                                        hotkeys.insert(new_code, (new, callback));
+                                        promise.set(Ok(()));
+                                    }
+                                    // Key presses are dispatched on this
+                                    // thread too, so no press can observe the
+                                    // hotkey while it's being moved.
+                                    let keys = hotkeys.keys().copied().collect();
+                                    grab_all(&xlib, display, keys);
+                                }
+                                Message::IsPressed(key, promise) => {
+                                    let code = (xlib.XKeysymToKeycode)(display, key as _) as usize;
+
+                                    // Each of the 256 key codes is represented
+                                    // by a bit in the returned key map.
+                                    let mut key_map = [0; 32];
+                                    (xlib.XQueryKeymap)(display, key_map.as_mut_ptr());
+                                    promise.set(
+                                        code != 0
+                                            && key_map[code / 8] as u8 & (1 << (code % 8)) != 0,
+                                    );
+                                }
+                                Message::Trigger(key, promise) => {
+                                    let code = (xlib.XKeysymToKeycode)(display, key as _) as c_uint;
+
+                                    if let Some((_, callback)) = hotkeys.get_mut(&code) {
+                                        callback(key, Duration::default());
+                                        promise.set(Ok(()));
+                                    } else {
+                                        promise.set(Err(Error::NotRegistered));
+                                    }
+                                }
+                                Message::End => {
+                                    break 'event_loop;
+                                }
+                            }
+                        }
+                    } else if mio_event.token() == X_TOKEN {
+                        while (xlib.XPending)(display) != 0 {
+                            let mut event = mem::MaybeUninit::uninit();
+                            (xlib.XNextEvent)(display, event.as_mut_ptr());
+                            let event = event.assume_init();
+                            if event.get_type() == KeyPress {
+                                let event: &XKeyEvent = event.as_ref();
+                                if let Some((key, callback)) = hotkeys.get_mut(&event.keycode) {
+                                    // The server time is in milliseconds of
+                                    // the monotonic clock and wraps around.
+                                    let now = monotonic_now().as_millis() as u32;
+                                    let latency = Duration::from_millis(
+                                        now.wrapping_sub(event.time as u32) as u64,
+                                    );
+                                    callback(*key, plausible_latency(latency));
+                                }
+                                // FIXME: We should check else here: these amount to lost
+                                // keypresses.
+                            }
+                        }
+                    }
+                }
+            }
+
+            ungrab_all(&xlib, display);
+
+            (xlib.XCloseDisplay)(display);
+
+            result
+        });
+
+        Ok(Hook {
+            backend: Backend::X11,
+            sender,
+            waker,
+            join_handle: Some(join_handle),
+        })
+    }
+}
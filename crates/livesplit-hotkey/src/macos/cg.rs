@@ -168,6 +168,15 @@ pub enum EventField {
     MouseEventWindowUnderMousePointerThatCanHandleThisEvent = 92,
 }
 
+#[repr(i32)]
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum EventSourceStateId {
+    Private = -1,
+    CombinedSessionState = 0,
+    HidSystemState = 1,
+}
+
 mod opaque {
     pub enum Event {}
     pub enum EventTapProxy {}
@@ -176,6 +185,8 @@ mod opaque {
 pub type EventRef = *mut opaque::Event;
 pub type EventTapProxy = *mut opaque::EventTapProxy;
 
+pub type EventTimestamp = u64;
+
 pub type EventTapCallBack = Option<
     unsafe extern "C" fn(
         proxy: EventTapProxy,
@@ -197,4 +208,8 @@ extern "C" {
     ) -> MachPortRef;
 
     pub fn CGEventGetIntegerValueField(event: EventRef, field: EventField) -> i64;
+
+    pub fn CGEventGetTimestamp(event: EventRef) -> EventTimestamp;
+
+    pub fn CGEventSourceKeyState(state_id: EventSourceStateId, key: u16) -> bool;
 }
@@ -0,0 +1,12 @@
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimebaseInfo {
+    pub numer: u32,
+    pub denom: u32,
+}
+
+extern "C" {
+    pub fn mach_absolute_time() -> u64;
+
+    pub fn mach_timebase_info(info: *mut TimebaseInfo) -> i32;
+}
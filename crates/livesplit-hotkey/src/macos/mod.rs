@@ -1,6 +1,7 @@
 mod cf;
 mod cg;
 mod key_code;
+mod mach;
 
 use self::{
     cf::{
@@ -9,33 +10,25 @@ use self::{
         CFRunLoopRun,
     },
     cg::{
-        CGEventTapCreate, EventMask, EventRef, EventTapLocation, EventTapOptions,
-        EventTapPlacement, EventTapProxy, EventType,
+        CGEventTapCreate, EventMask, EventRef, EventSourceStateId, EventTapLocation,
+        EventTapOptions, EventTapPlacement, EventTapProxy, EventType,
     },
+    mach::{mach_absolute_time, mach_timebase_info, TimebaseInfo},
 };
+use crate::{plausible_latency, Backend, Error, Result};
 use cg::EventField;
 use parking_lot::Mutex;
 use std::{
     collections::{hash_map::Entry, HashMap},
     ffi::c_void,
+    ptr,
     sync::{mpsc::channel, Arc},
     thread,
+    time::Duration,
 };
 
 pub use self::key_code::KeyCode;
 
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {
-    AlreadyRegistered,
-    NotRegistered,
-    CouldntCreateEventTap,
-    CouldntCreateRunLoopSource,
-    CouldntGetCurrentRunLoop,
-    ThreadStoppedUnexpectedly,
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
-
 struct Owned<T>(*mut T);
 
 impl<T> Drop for Owned<T> {
@@ -51,7 +44,17 @@ struct RunLoop(cf::RunLoopRef);
 
 unsafe impl Send for RunLoop {}
 
-type RegisteredKeys = Mutex<HashMap<KeyCode, Box<dyn FnMut() + Send + 'static>>>;
+struct Hotkey {
+    callback: Box<dyn FnMut(KeyCode, Duration) + Send + 'static>,
+    consume: bool,
+}
+
+type RegisteredKeys = Mutex<HashMap<KeyCode, Hotkey>>;
+
+struct TapState {
+    hotkeys: Arc<RegisteredKeys>,
+    timebase: TimebaseInfo,
+}
 
 pub struct Hook {
     event_loop: RunLoop,
@@ -81,7 +84,14 @@ impl Hook {
         // https://github.com/kwhat/libuiohook/blob/f4bb19be8aee7d7ee5ead89b5a89dbf440e2a71a/src/darwin/input_hook.c#L1086
 
         thread::spawn(move || unsafe {
-            let hotkeys_ptr: *const Mutex<_> = &*thread_hotkeys;
+            let mut timebase = TimebaseInfo::default();
+            mach_timebase_info(&mut timebase);
+
+            let state = TapState {
+                hotkeys: thread_hotkeys,
+                timebase,
+            };
+            let state_ptr: *const TapState = &state;
 
             let port = CGEventTapCreate(
                 EventTapLocation::Session,
@@ -89,24 +99,30 @@ impl Hook {
                 EventTapOptions::DefaultTap,
                 EventMask::KEY_DOWN,
                 Some(callback),
-                hotkeys_ptr as *mut c_void,
+                state_ptr as *mut c_void,
             );
             if port.is_null() {
-                let _ = sender.send(Err(Error::CouldntCreateEventTap));
+                // Creating the event tap fails if the application isn't
+                // trusted for accessibility.
+                let _ = sender.send(Err(Error::PermissionDenied));
                 return;
             }
             let port = Owned(port);
 
             let source = CFMachPortCreateRunLoopSource(kCFAllocatorDefault, port.0, 0);
             if source.is_null() {
-                let _ = sender.send(Err(Error::CouldntCreateRunLoopSource));
+                let _ = sender.send(Err(Error::BackendUnavailable {
+                    reason: "Couldn't create the run loop source.",
+                }));
                 return;
             }
             let source = Owned(source);
 
             let event_loop = CFRunLoopGetCurrent();
             if event_loop.is_null() {
-                let _ = sender.send(Err(Error::CouldntGetCurrentRunLoop));
+                let _ = sender.send(Err(Error::BackendUnavailable {
+                    reason: "Couldn't get the current run loop.",
+                }));
                 return;
             }
 
@@ -121,9 +137,7 @@ impl Hook {
             }
         });
 
-        let event_loop = receiver
-            .recv()
-            .map_err(|_| Error::ThreadStoppedUnexpectedly)??;
+        let event_loop = receiver.recv().map_err(|_| Error::ThreadStopped)??;
 
         Ok(Hook {
             event_loop,
@@ -131,12 +145,33 @@ impl Hook {
         })
     }
 
+    pub fn backend(&self) -> Backend {
+        Backend::MacOS
+    }
+
     pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
     where
-        F: FnMut() + Send + 'static,
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, false, callback)
+    }
+
+    pub fn register_consuming<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, true, callback)
+    }
+
+    fn register_with<F>(&self, hotkey: KeyCode, consume: bool, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
     {
         if let Entry::Vacant(vacant) = self.hotkeys.lock().entry(hotkey) {
-            vacant.insert(Box::new(callback));
+            vacant.insert(Hotkey {
+                callback: Box::new(callback),
+                consume,
+            });
             Ok(())
         } else {
             Err(Error::AlreadyRegistered)
@@ -150,6 +185,42 @@ impl Hook {
             Err(Error::NotRegistered)
         }
     }
+
+    pub fn rebind(&self, old: KeyCode, new: KeyCode) -> Result<()> {
+        let mut hotkeys = self.hotkeys.lock();
+        if !hotkeys.contains_key(&old) {
+            return Err(Error::NotRegistered);
+        }
+        if old == new {
+            return Ok(());
+        }
+        if hotkeys.contains_key(&new) {
+            return Err(Error::AlreadyRegistered);
+        }
+        let callback = hotkeys.remove(&old).unwrap();
+        hotkeys.insert(new, callback);
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, hotkey: KeyCode) -> bool {
+        // The virtual key codes are all below 0x80, so we can find the one
+        // for the key by searching through them.
+        match (0..0x80).find(|&raw| to_key_code(raw) == Some(hotkey)) {
+            Some(raw) => unsafe {
+                cg::CGEventSourceKeyState(EventSourceStateId::CombinedSessionState, raw as u16)
+            },
+            None => false,
+        }
+    }
+
+    pub fn trigger(&self, hotkey: KeyCode) -> Result<()> {
+        if let Some(registered) = self.hotkeys.lock().get_mut(&hotkey) {
+            (registered.callback)(hotkey, Duration::default());
+            Ok(())
+        } else {
+            Err(Error::NotRegistered)
+        }
+    }
 }
 
 unsafe extern "C" fn callback(
@@ -160,134 +231,157 @@ unsafe extern "C" fn callback(
 ) -> EventRef {
     if matches!(ty, EventType::KeyDown) {
         let key_code = cg::CGEventGetIntegerValueField(event, EventField::KeyboardEventKeycode);
-        let key_code = match key_code {
-            0x00 => KeyCode::A,
-            0x01 => KeyCode::S,
-            0x02 => KeyCode::D,
-            0x03 => KeyCode::F,
-            0x04 => KeyCode::H,
-            0x05 => KeyCode::G,
-            0x06 => KeyCode::Z,
-            0x07 => KeyCode::X,
-            0x08 => KeyCode::C,
-            0x09 => KeyCode::V,
-            0x0A => KeyCode::IsoSection,
-            0x0B => KeyCode::B,
-            0x0C => KeyCode::Q,
-            0x0D => KeyCode::W,
-            0x0E => KeyCode::E,
-            0x0F => KeyCode::R,
-            0x10 => KeyCode::Y,
-            0x11 => KeyCode::T,
-            0x12 => KeyCode::Digit1,
-            0x13 => KeyCode::Digit2,
-            0x14 => KeyCode::Digit3,
-            0x15 => KeyCode::Digit4,
-            0x16 => KeyCode::Digit6,
-            0x17 => KeyCode::Digit5,
-            0x18 => KeyCode::Equal,
-            0x19 => KeyCode::Digit9,
-            0x1A => KeyCode::Digit7,
-            0x1B => KeyCode::Minus,
-            0x1C => KeyCode::Digit8,
-            0x1D => KeyCode::Digit0,
-            0x1E => KeyCode::RightBracket,
-            0x1F => KeyCode::O,
-            0x20 => KeyCode::U,
-            0x21 => KeyCode::LeftBracket,
-            0x22 => KeyCode::I,
-            0x23 => KeyCode::P,
-            0x24 => KeyCode::Return,
-            0x25 => KeyCode::L,
-            0x26 => KeyCode::J,
-            0x27 => KeyCode::Quote,
-            0x28 => KeyCode::K,
-            0x29 => KeyCode::Semicolon,
-            0x2A => KeyCode::Backslash,
-            0x2B => KeyCode::Comma,
-            0x2C => KeyCode::Slash,
-            0x2D => KeyCode::N,
-            0x2E => KeyCode::M,
-            0x2F => KeyCode::Period,
-            0x30 => KeyCode::Tab,
-            0x31 => KeyCode::Space,
-            0x32 => KeyCode::Grave,
-            0x33 => KeyCode::Delete,
-            0x35 => KeyCode::Escape,
-            0x36 => KeyCode::RightCommand,
-            0x37 => KeyCode::Command,
-            0x38 => KeyCode::Shift,
-            0x39 => KeyCode::CapsLock,
-            0x3A => KeyCode::Option,
-            0x3B => KeyCode::Control,
-            0x3C => KeyCode::RightShift,
-            0x3D => KeyCode::RightOption,
-            0x3E => KeyCode::RightControl,
-            0x3F => KeyCode::Function,
-            0x40 => KeyCode::F17,
-            0x41 => KeyCode::NumpadDecimal,
-            0x43 => KeyCode::NumpadMultiply,
-            0x45 => KeyCode::NumpadPlus,
-            0x47 => KeyCode::NumpadClear,
-            0x48 => KeyCode::VolumeUp,
-            0x49 => KeyCode::VolumeDown,
-            0x4A => KeyCode::Mute,
-            0x4B => KeyCode::NumpadDivide,
-            0x4C => KeyCode::NumpadEnter,
-            0x4E => KeyCode::NumpadMinus,
-            0x4F => KeyCode::F18,
-            0x50 => KeyCode::F19,
-            0x51 => KeyCode::NumpadEquals,
-            0x52 => KeyCode::Numpad0,
-            0x53 => KeyCode::Numpad1,
-            0x54 => KeyCode::Numpad2,
-            0x55 => KeyCode::Numpad3,
-            0x56 => KeyCode::Numpad4,
-            0x57 => KeyCode::Numpad5,
-            0x58 => KeyCode::Numpad6,
-            0x59 => KeyCode::Numpad7,
-            0x5A => KeyCode::F20,
-            0x5B => KeyCode::Numpad8,
-            0x5C => KeyCode::Numpad9,
-            0x5D => KeyCode::JisYen,
-            0x5E => KeyCode::JisUnderscore,
-            0x5F => KeyCode::JisKeypadComma,
-            0x60 => KeyCode::F5,
-            0x61 => KeyCode::F6,
-            0x62 => KeyCode::F7,
-            0x63 => KeyCode::F3,
-            0x64 => KeyCode::F8,
-            0x65 => KeyCode::F9,
-            0x66 => KeyCode::JisEisu,
-            0x67 => KeyCode::F11,
-            0x68 => KeyCode::JisKana,
-            0x69 => KeyCode::F13,
-            0x6A => KeyCode::F16,
-            0x6B => KeyCode::F14,
-            0x6D => KeyCode::F10,
-            0x6F => KeyCode::F12,
-            0x71 => KeyCode::F15,
-            0x72 => KeyCode::Help,
-            0x73 => KeyCode::Home,
-            0x74 => KeyCode::PageUp,
-            0x75 => KeyCode::ForwardDelete,
-            0x76 => KeyCode::F4,
-            0x77 => KeyCode::End,
-            0x78 => KeyCode::F2,
-            0x79 => KeyCode::PageDown,
-            0x7A => KeyCode::F1,
-            0x7B => KeyCode::LeftArrow,
-            0x7C => KeyCode::RightArrow,
-            0x7D => KeyCode::DownArrow,
-            0x7E => KeyCode::UpArrow,
-            _ => return event,
+        let key_code = match to_key_code(key_code) {
+            Some(key_code) => key_code,
+            None => return event,
         };
 
-        let hotkeys = user_info as *const RegisteredKeys;
-        let hotkeys = &*hotkeys;
-        if let Some(callback) = hotkeys.lock().get_mut(&key_code) {
-            callback();
+        let state = &*(user_info as *const TapState);
+        if let Some(hotkey) = state.hotkeys.lock().get_mut(&key_code) {
+            (hotkey.callback)(key_code, latency(event, state.timebase));
+            if hotkey.consume {
+                // Returning null from an active event tap deletes the event,
+                // so it never reaches the focused application.
+                return ptr::null_mut();
+            }
         }
     }
     event
 }
+
+unsafe fn latency(event: EventRef, timebase: TimebaseInfo) -> Duration {
+    if timebase.denom == 0 {
+        return Duration::default();
+    }
+    // The event's timestamp is in the same ticks as the mach absolute time.
+    // These are only nanoseconds on Intel, so the difference gets converted
+    // afterwards.
+    let ticks = mach_absolute_time().saturating_sub(cg::CGEventGetTimestamp(event));
+    let nanos = ticks as u128 * timebase.numer as u128 / timebase.denom as u128;
+    plausible_latency(Duration::from_nanos(nanos.min(u64::MAX as u128) as u64))
+}
+
+fn to_key_code(raw: i64) -> Option<KeyCode> {
+    Some(match raw {
+        0x00 => KeyCode::A,
+        0x01 => KeyCode::S,
+        0x02 => KeyCode::D,
+        0x03 => KeyCode::F,
+        0x04 => KeyCode::H,
+        0x05 => KeyCode::G,
+        0x06 => KeyCode::Z,
+        0x07 => KeyCode::X,
+        0x08 => KeyCode::C,
+        0x09 => KeyCode::V,
+        0x0A => KeyCode::IsoSection,
+        0x0B => KeyCode::B,
+        0x0C => KeyCode::Q,
+        0x0D => KeyCode::W,
+        0x0E => KeyCode::E,
+        0x0F => KeyCode::R,
+        0x10 => KeyCode::Y,
+        0x11 => KeyCode::T,
+        0x12 => KeyCode::Digit1,
+        0x13 => KeyCode::Digit2,
+        0x14 => KeyCode::Digit3,
+        0x15 => KeyCode::Digit4,
+        0x16 => KeyCode::Digit6,
+        0x17 => KeyCode::Digit5,
+        0x18 => KeyCode::Equal,
+        0x19 => KeyCode::Digit9,
+        0x1A => KeyCode::Digit7,
+        0x1B => KeyCode::Minus,
+        0x1C => KeyCode::Digit8,
+        0x1D => KeyCode::Digit0,
+        0x1E => KeyCode::RightBracket,
+        0x1F => KeyCode::O,
+        0x20 => KeyCode::U,
+        0x21 => KeyCode::LeftBracket,
+        0x22 => KeyCode::I,
+        0x23 => KeyCode::P,
+        0x24 => KeyCode::Return,
+        0x25 => KeyCode::L,
+        0x26 => KeyCode::J,
+        0x27 => KeyCode::Quote,
+        0x28 => KeyCode::K,
+        0x29 => KeyCode::Semicolon,
+        0x2A => KeyCode::Backslash,
+        0x2B => KeyCode::Comma,
+        0x2C => KeyCode::Slash,
+        0x2D => KeyCode::N,
+        0x2E => KeyCode::M,
+        0x2F => KeyCode::Period,
+        0x30 => KeyCode::Tab,
+        0x31 => KeyCode::Space,
+        0x32 => KeyCode::Grave,
+        0x33 => KeyCode::Delete,
+        0x35 => KeyCode::Escape,
+        0x36 => KeyCode::RightCommand,
+        0x37 => KeyCode::Command,
+        0x38 => KeyCode::Shift,
+        0x39 => KeyCode::CapsLock,
+        0x3A => KeyCode::Option,
+        0x3B => KeyCode::Control,
+        0x3C => KeyCode::RightShift,
+        0x3D => KeyCode::RightOption,
+        0x3E => KeyCode::RightControl,
+        0x3F => KeyCode::Function,
+        0x40 => KeyCode::F17,
+        0x41 => KeyCode::NumpadDecimal,
+        0x43 => KeyCode::NumpadMultiply,
+        0x45 => KeyCode::NumpadPlus,
+        0x47 => KeyCode::NumpadClear,
+        0x48 => KeyCode::VolumeUp,
+        0x49 => KeyCode::VolumeDown,
+        0x4A => KeyCode::Mute,
+        0x4B => KeyCode::NumpadDivide,
+        0x4C => KeyCode::NumpadEnter,
+        0x4E => KeyCode::NumpadMinus,
+        0x4F => KeyCode::F18,
+        0x50 => KeyCode::F19,
+        0x51 => KeyCode::NumpadEquals,
+        0x52 => KeyCode::Numpad0,
+        0x53 => KeyCode::Numpad1,
+        0x54 => KeyCode::Numpad2,
+        0x55 => KeyCode::Numpad3,
+        0x56 => KeyCode::Numpad4,
+        0x57 => KeyCode::Numpad5,
+        0x58 => KeyCode::Numpad6,
+        0x59 => KeyCode::Numpad7,
+        0x5A => KeyCode::F20,
+        0x5B => KeyCode::Numpad8,
+        0x5C => KeyCode::Numpad9,
+        0x5D => KeyCode::JisYen,
+        0x5E => KeyCode::JisUnderscore,
+        0x5F => KeyCode::JisKeypadComma,
+        0x60 => KeyCode::F5,
+        0x61 => KeyCode::F6,
+        0x62 => KeyCode::F7,
+        0x63 => KeyCode::F3,
+        0x64 => KeyCode::F8,
+        0x65 => KeyCode::F9,
+        0x66 => KeyCode::JisEisu,
+        0x67 => KeyCode::F11,
+        0x68 => KeyCode::JisKana,
+        0x69 => KeyCode::F13,
+        0x6A => KeyCode::F16,
+        0x6B => KeyCode::F14,
+        0x6D => KeyCode::F10,
+        0x6F => KeyCode::F12,
+        0x71 => KeyCode::F15,
+        0x72 => KeyCode::Help,
+        0x73 => KeyCode::Home,
+        0x74 => KeyCode::PageUp,
+        0x75 => KeyCode::ForwardDelete,
+        0x76 => KeyCode::F4,
+        0x77 => KeyCode::End,
+        0x78 => KeyCode::F2,
+        0x79 => KeyCode::PageDown,
+        0x7A => KeyCode::F1,
+        0x7B => KeyCode::LeftArrow,
+        0x7C => KeyCode::RightArrow,
+        0x7D => KeyCode::DownArrow,
+        0x7E => KeyCode::UpArrow,
+        _ => return None,
+    })
+}
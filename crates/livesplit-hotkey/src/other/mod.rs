@@ -1,7 +1,5 @@
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {}
-
-pub type Result<T> = StdResult<T, Error>;
+use crate::{Backend, Error, Result};
+use core::time::Duration;
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone, serde::Serialize, serde::Deserialize)]
 pub struct KeyCode;
@@ -13,9 +11,20 @@ impl Hook {
         Ok(Hook)
     }
 
+    pub fn backend(&self) -> Backend {
+        Backend::Unsupported
+    }
+
     pub fn register<F>(&self, _: KeyCode, _: F) -> Result<()>
     where
-        F: FnMut() + Send + 'static,
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        Ok(())
+    }
+
+    pub fn register_consuming<F>(&self, _: KeyCode, _: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
     {
         Ok(())
     }
@@ -23,6 +32,23 @@ impl Hook {
     pub fn unregister(&self, _: KeyCode) -> Result<()> {
         Ok(())
     }
+
+    pub fn rebind(&self, _: KeyCode, _: KeyCode) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, _: KeyCode) -> bool {
+        false
+    }
+
+    pub fn trigger(&self, _: KeyCode) -> Result<()> {
+        // The callbacks are never stored, so there's nothing that could run.
+        // Reporting this keeps tests of the hotkey wiring from silently
+        // passing on this backend.
+        Err(Error::BackendUnavailable {
+            reason: "Hotkeys are not supported on this platform.",
+        })
+    }
 }
 
 use core::{result::Result as StdResult, str::FromStr};
@@ -33,3 +59,13 @@ impl FromStr for KeyCode {
         Ok(KeyCode)
     }
 }
+
+#[test]
+fn trigger_reports_unsupported_backend() {
+    let hook = Hook::new().unwrap();
+    hook.register(KeyCode, |_, _| {}).unwrap();
+    assert!(matches!(
+        hook.trigger(KeyCode),
+        Err(Error::BackendUnavailable { .. })
+    ));
+}
@@ -1,22 +1,15 @@
 mod key_code;
 pub use self::key_code::KeyCode;
 
+use crate::{Backend, Error, Result};
 use std::collections::hash_map::{Entry, HashMap};
 use std::sync::{Arc, Mutex};
-use std::{slice, str};
-
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {
-    AlreadyRegistered,
-    NotRegistered,
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
+use std::{slice, str, time::Duration};
 
 pub type EventListenerHandle = Box<dyn Fn(&str)>;
 
 pub struct Hook {
-    hotkeys: Arc<Mutex<HashMap<KeyCode, Box<dyn FnMut() + Send + 'static>>>>,
+    hotkeys: Arc<Mutex<HashMap<KeyCode, Box<dyn FnMut(KeyCode, Duration) + Send + 'static>>>>,
     event: Option<Box<EventListenerHandle>>,
 }
 
@@ -49,14 +42,16 @@ impl Hook {
     pub fn new() -> Result<Self> {
         let hotkeys = Arc::new(Mutex::new(HashMap::<
             KeyCode,
-            Box<dyn FnMut() + Send + 'static>,
+            Box<dyn FnMut(KeyCode, Duration) + Send + 'static>,
         >::new()));
 
         let hotkey_map = hotkeys.clone();
         let event = Box::new(Box::new(move |code: &str| {
             if let Ok(code) = code.parse() {
                 if let Some(callback) = hotkey_map.lock().unwrap().get_mut(&code) {
-                    callback();
+                    // The JavaScript host doesn't provide a timestamp for the
+                    // event.
+                    callback(code, Duration::default());
                 }
             }
         }) as EventListenerHandle);
@@ -71,9 +66,13 @@ impl Hook {
         })
     }
 
+    pub fn backend(&self) -> Backend {
+        Backend::WasmHost
+    }
+
     pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
     where
-        F: FnMut() + Send + 'static,
+        F: FnMut(KeyCode, Duration) + Send + 'static,
     {
         if let Entry::Vacant(vacant) = self.hotkeys.lock().unwrap().entry(hotkey) {
             vacant.insert(Box::new(callback));
@@ -83,6 +82,14 @@ impl Hook {
         }
     }
 
+    pub fn register_consuming<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        // The JavaScript host decides whether the event gets propagated.
+        self.register(hotkey, callback)
+    }
+
     pub fn unregister(&self, hotkey: KeyCode) -> Result<()> {
         if self.hotkeys.lock().unwrap().remove(&hotkey).is_some() {
             Ok(())
@@ -90,4 +97,34 @@ impl Hook {
             Err(Error::NotRegistered)
         }
     }
+
+    pub fn rebind(&self, old: KeyCode, new: KeyCode) -> Result<()> {
+        let mut hotkeys = self.hotkeys.lock().unwrap();
+        if !hotkeys.contains_key(&old) {
+            return Err(Error::NotRegistered);
+        }
+        if old == new {
+            return Ok(());
+        }
+        if hotkeys.contains_key(&new) {
+            return Err(Error::AlreadyRegistered);
+        }
+        let callback = hotkeys.remove(&old).unwrap();
+        hotkeys.insert(new, callback);
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, _: KeyCode) -> bool {
+        // The JavaScript host doesn't tell us about the state of the keys.
+        false
+    }
+
+    pub fn trigger(&self, hotkey: KeyCode) -> Result<()> {
+        if let Some(callback) = self.hotkeys.lock().unwrap().get_mut(&hotkey) {
+            callback(hotkey, Duration::default());
+            Ok(())
+        } else {
+            Err(Error::NotRegistered)
+        }
+    }
 }
@@ -1,28 +1,46 @@
 mod key_code;
 pub use self::key_code::KeyCode;
 
+use crate::{plausible_latency, Backend, Error, Result};
 use wasm_bindgen::{prelude::*, JsCast};
-use web_sys::{window, Gamepad, GamepadButton, KeyboardEvent};
+use web_sys::{window, Gamepad, GamepadButton, KeyboardEvent, Performance};
 
 use std::{
     array,
     cell::Cell,
-    collections::hash_map::{Entry, HashMap},
+    collections::{
+        hash_map::{Entry, HashMap},
+        HashSet,
+    },
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {
-    AlreadyRegistered,
-    NotRegistered,
-    FailedToCreateHook,
+const NO_WINDOW: Error = Error::BackendUnavailable {
+    reason: "There is no browser window.",
+};
+
+// Both the event and the gamepad timestamps are measured in milliseconds
+// relative to the same origin as `performance.now()`.
+fn latency_since(performance: &Option<Performance>, timestamp: f64) -> Duration {
+    match performance {
+        Some(performance) => plausible_latency(Duration::from_secs_f64(
+            (performance.now() - timestamp).max(0.0) / 1000.0,
+        )),
+        None => Duration::default(),
+    }
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
+struct Hotkey {
+    callback: Box<dyn FnMut(KeyCode, Duration) + Send + 'static>,
+    consume: bool,
+}
 
 pub struct Hook {
-    hotkeys: Arc<Mutex<HashMap<KeyCode, Box<dyn FnMut() + Send + 'static>>>>,
+    hotkeys: Arc<Mutex<HashMap<KeyCode, Hotkey>>>,
     keyboard_callback: Closure<dyn FnMut(KeyboardEvent)>,
+    key_state_callback: Closure<dyn FnMut(KeyboardEvent)>,
+    pressed_keys: Arc<Mutex<HashSet<KeyCode>>>,
     gamepad_callback: Closure<dyn FnMut()>,
     interval_id: Cell<Option<i32>>,
 }
@@ -34,6 +52,12 @@ impl Drop for Hook {
                 "keypress",
                 self.keyboard_callback.as_ref().unchecked_ref(),
             );
+            for event in &["keydown", "keyup"] {
+                let _ = window.remove_event_listener_with_callback(
+                    event,
+                    self.key_state_callback.as_ref().unchecked_ref(),
+                );
+            }
             if let Some(interval_id) = self.interval_id.get() {
                 window.clear_interval_with_handle(interval_id);
             }
@@ -67,18 +91,19 @@ static GAMEPAD_BUTTONS: [KeyCode; TOTAL_BUTTONS] = [
 
 impl Hook {
     pub fn new() -> Result<Self> {
-        let hotkeys = Arc::new(Mutex::new(HashMap::<
-            KeyCode,
-            Box<dyn FnMut() + Send + 'static>,
-        >::new()));
+        let hotkeys = Arc::new(Mutex::new(HashMap::<KeyCode, Hotkey>::new()));
 
-        let window = window().ok_or(Error::FailedToCreateHook)?;
+        let window = window().ok_or(NO_WINDOW)?;
 
         let hotkey_map = hotkeys.clone();
+        let performance = window.performance();
         let keyboard_callback = Closure::wrap(Box::new(move |event: KeyboardEvent| {
             if let Ok(code) = event.code().parse() {
-                if let Some(callback) = hotkey_map.lock().unwrap().get_mut(&code) {
-                    callback();
+                if let Some(hotkey) = hotkey_map.lock().unwrap().get_mut(&code) {
+                    // Consumed hotkeys are handled on key down instead.
+                    if !hotkey.consume {
+                        (hotkey.callback)(code, latency_since(&performance, event.time_stamp()));
+                    }
                 }
             }
         }) as Box<dyn FnMut(KeyboardEvent)>);
@@ -88,12 +113,64 @@ impl Hook {
                 "keypress",
                 keyboard_callback.as_ref().unchecked_ref(),
             )
-            .map_err(|_| Error::FailedToCreateHook)?;
+            .map_err(|_| Error::BackendUnavailable {
+                reason: "Couldn't listen for keyboard events.",
+            })?;
+
+        let pressed_keys = Arc::new(Mutex::new(HashSet::new()));
+
+        // The browser only reports the keys while the page is focused, so this
+        // is the best we can do for tracking which keys are held down.
+        let pressed = pressed_keys.clone();
+        let hotkey_map = hotkeys.clone();
+        let performance = window.performance();
+        let key_state_callback = Closure::wrap(Box::new(move |event: KeyboardEvent| {
+            if let Ok(code) = event.code().parse() {
+                let is_down = event.type_() == "keydown";
+                {
+                    let mut pressed = pressed.lock().unwrap();
+                    if is_down {
+                        pressed.insert(code);
+                    } else {
+                        pressed.remove(&code);
+                    }
+                }
+
+                // Preventing the key down keeps the key from reaching the page
+                // at all, but it also suppresses the key press event, so the
+                // consumed hotkeys need to fire here.
+                if is_down {
+                    if let Some(hotkey) = hotkey_map.lock().unwrap().get_mut(&code) {
+                        if hotkey.consume {
+                            event.prevent_default();
+                            if !event.repeat() {
+                                (hotkey.callback)(
+                                    code,
+                                    latency_since(&performance, event.time_stamp()),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }) as Box<dyn FnMut(KeyboardEvent)>);
+
+        for event in &["keydown", "keyup"] {
+            window
+                .add_event_listener_with_callback(
+                    event,
+                    key_state_callback.as_ref().unchecked_ref(),
+                )
+                .map_err(|_| Error::BackendUnavailable {
+                    reason: "Couldn't listen for keyboard events.",
+                })?;
+        }
 
         let hotkey_map = hotkeys.clone();
 
         let mut states = Vec::new();
         let navigator = window.navigator();
+        let performance = window.performance();
 
         let gamepad_callback = Closure::wrap(Box::new(move || {
             if let Ok(gamepads) = navigator.get_gamepads() {
@@ -112,10 +189,11 @@ impl Hook {
                             if let Ok(button) = button.dyn_into::<GamepadButton>() {
                                 let pressed = button.pressed();
                                 if pressed && !*state {
-                                    if let Some(callback) =
-                                        hotkey_map.lock().unwrap().get_mut(&code)
+                                    if let Some(hotkey) = hotkey_map.lock().unwrap().get_mut(&code)
                                     {
-                                        callback();
+                                        let latency =
+                                            latency_since(&performance, gamepad.timestamp());
+                                        (hotkey.callback)(code, latency);
                                     }
                                 }
                                 *state = pressed;
@@ -129,33 +207,63 @@ impl Hook {
         Ok(Hook {
             hotkeys,
             keyboard_callback,
+            key_state_callback,
+            pressed_keys,
             gamepad_callback,
             interval_id: Cell::new(None),
         })
     }
 
+    pub fn backend(&self) -> Backend {
+        Backend::Web
+    }
+
     pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
     where
-        F: FnMut() + Send + 'static,
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, false, callback)
+    }
+
+    pub fn register_consuming<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, true, callback)
+    }
+
+    fn register_with<F>(&self, hotkey: KeyCode, consume: bool, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
     {
         if let Entry::Vacant(vacant) = self.hotkeys.lock().unwrap().entry(hotkey) {
-            if GAMEPAD_BUTTONS.contains(&hotkey) && self.interval_id.get().is_none() {
-                let interval_id = window()
-                    .ok_or(Error::FailedToCreateHook)?
-                    .set_interval_with_callback_and_timeout_and_arguments_0(
-                        self.gamepad_callback.as_ref().unchecked_ref(),
-                        1000 / 60,
-                    )
-                    .map_err(|_| Error::FailedToCreateHook)?;
-                self.interval_id.set(Some(interval_id));
-            }
-            vacant.insert(Box::new(callback));
+            self.poll_gamepads_for(hotkey)?;
+            vacant.insert(Hotkey {
+                callback: Box::new(callback),
+                consume,
+            });
             Ok(())
         } else {
             Err(Error::AlreadyRegistered)
         }
     }
 
+    fn poll_gamepads_for(&self, hotkey: KeyCode) -> Result<()> {
+        if GAMEPAD_BUTTONS.contains(&hotkey) && self.interval_id.get().is_none() {
+            let interval_id = window()
+                .ok_or(NO_WINDOW)?
+                .set_interval_with_callback_and_timeout_and_arguments_0(
+                    self.gamepad_callback.as_ref().unchecked_ref(),
+                    1000 / 60,
+                )
+                .map_err(|_| Error::BackendUnavailable {
+                    reason: "Couldn't start polling the gamepads.",
+                })?;
+            self.interval_id.set(Some(interval_id));
+        }
+        Ok(())
+    }
+
     pub fn unregister(&self, hotkey: KeyCode) -> Result<()> {
         if self.hotkeys.lock().unwrap().remove(&hotkey).is_some() {
             Ok(())
@@ -163,4 +271,52 @@ impl Hook {
             Err(Error::NotRegistered)
         }
     }
+
+    pub fn rebind(&self, old: KeyCode, new: KeyCode) -> Result<()> {
+        let mut hotkeys = self.hotkeys.lock().unwrap();
+        if !hotkeys.contains_key(&old) {
+            return Err(Error::NotRegistered);
+        }
+        if old == new {
+            return Ok(());
+        }
+        if hotkeys.contains_key(&new) {
+            return Err(Error::AlreadyRegistered);
+        }
+        self.poll_gamepads_for(new)?;
+        let callback = hotkeys.remove(&old).unwrap();
+        hotkeys.insert(new, callback);
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, hotkey: KeyCode) -> bool {
+        if let Some(index) = GAMEPAD_BUTTONS.iter().position(|&button| button == hotkey) {
+            let gamepads = match window().and_then(|window| window.navigator().get_gamepads().ok())
+            {
+                Some(gamepads) => gamepads,
+                None => return false,
+            };
+            gamepads.iter().any(|gamepad| {
+                let button = gamepad.dyn_into::<Gamepad>().ok().and_then(|gamepad| {
+                    gamepad
+                        .buttons()
+                        .get(index as u32)
+                        .dyn_into::<GamepadButton>()
+                        .ok()
+                });
+                matches!(button, Some(button) if button.pressed())
+            })
+        } else {
+            self.pressed_keys.lock().unwrap().contains(&hotkey)
+        }
+    }
+
+    pub fn trigger(&self, hotkey: KeyCode) -> Result<()> {
+        if let Some(registered) = self.hotkeys.lock().unwrap().get_mut(&hotkey) {
+            (registered.callback)(hotkey, Duration::default());
+            Ok(())
+        } else {
+            Err(Error::NotRegistered)
+        }
+    }
 }
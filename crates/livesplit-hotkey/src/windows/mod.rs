@@ -1,16 +1,21 @@
 mod key_code;
 pub use self::key_code::KeyCode;
 
+use crate::{plausible_latency, Backend, Error, Result};
 use parking_lot::Mutex;
 use std::{
     cell::RefCell,
-    collections::hash_map::{Entry, HashMap},
+    collections::{
+        hash_map::{Entry, HashMap},
+        HashSet,
+    },
     mem, ptr,
     sync::{
         mpsc::{channel, Sender},
         Arc,
     },
     thread,
+    time::{Duration, Instant},
 };
 use winapi::{
     ctypes::c_int,
@@ -21,29 +26,22 @@ use winapi::{
     um::{
         libloaderapi::GetModuleHandleW,
         processthreadsapi::GetCurrentThreadId,
+        sysinfoapi::GetTickCount,
         winuser::{
-            CallNextHookEx, GetMessageW, PostThreadMessageW, SetWindowsHookExW,
-            UnhookWindowsHookEx, KBDLLHOOKSTRUCT, WH_KEYBOARD_LL, WM_KEYDOWN,
+            CallNextHookEx, GetAsyncKeyState, GetMessageW, PostThreadMessageW, SetWindowsHookExW,
+            UnhookWindowsHookEx, KBDLLHOOKSTRUCT, WH_KEYBOARD_LL, WM_KEYDOWN, WM_KEYUP,
+            WM_SYSKEYDOWN, WM_SYSKEYUP,
         },
     },
 };
 
 const MSG_EXIT: UINT = 0x400;
 
-#[derive(Debug, snafu::Snafu)]
-pub enum Error {
-    AlreadyRegistered,
-    NotRegistered,
-    WindowsHook,
-    ThreadStopped,
-    MessageLoop,
-}
-
-pub type Result<T> = std::result::Result<T, Error>;
-
 pub struct Hook {
     thread_id: DWORD,
-    hotkeys: Arc<Mutex<HashMap<KeyCode, Box<dyn FnMut() + Send + 'static>>>>,
+    hotkeys: Arc<Mutex<HashMap<KeyCode, Box<dyn FnMut(KeyCode, Duration) + Send + 'static>>>>,
+    consumed: Arc<Mutex<HashSet<KeyCode>>>,
+    pressed: Arc<Mutex<HashSet<KeyCode>>>,
 }
 
 impl Drop for Hook {
@@ -56,7 +54,9 @@ impl Drop for Hook {
 
 struct State {
     hook: HHOOK,
-    events: Sender<KeyCode>,
+    events: Sender<(KeyCode, Instant)>,
+    consumed: Arc<Mutex<HashSet<KeyCode>>>,
+    pressed: Arc<Mutex<HashSet<KeyCode>>>,
 }
 
 thread_local! {
@@ -73,12 +73,33 @@ unsafe extern "system" fn callback_proc(code: c_int, wparam: WPARAM, lparam: LPA
             if hook_struct.vkCode >= 1 && hook_struct.vkCode <= 0xFE {
                 let key_code = mem::transmute(hook_struct.vkCode as u8);
                 let event = wparam as UINT;
+
+                // The system doesn't update its own key state for the keys we
+                // consume, so we keep track of them ourselves.
+                if event == WM_KEYDOWN || event == WM_SYSKEYDOWN {
+                    state.pressed.lock().insert(key_code);
+                } else if event == WM_KEYUP || event == WM_SYSKEYUP {
+                    state.pressed.lock().remove(&key_code);
+                }
+
                 if event == WM_KEYDOWN {
+                    // The event's time is in the same milliseconds since
+                    // system start that `GetTickCount` returns.
+                    let latency = GetTickCount().wrapping_sub(hook_struct.time);
+                    let latency = plausible_latency(Duration::from_millis(latency as u64));
+                    let now = Instant::now();
+                    let pressed_at = now.checked_sub(latency).unwrap_or(now);
                     state
                         .events
-                        .send(key_code)
+                        .send((key_code, pressed_at))
                         .expect("Callback Thread disconnected");
                 }
+                // Returning a non-zero value without calling the next hook
+                // prevents the event from reaching the focused window. This
+                // includes the key up events, so no stray key ups get through.
+                if state.consumed.lock().contains(&key_code) {
+                    return 1;
+                }
             }
         }
 
@@ -90,9 +111,15 @@ impl Hook {
     pub fn new() -> Result<Self> {
         let hotkeys = Arc::new(Mutex::new(HashMap::<
             KeyCode,
-            Box<dyn FnMut() + Send + 'static>,
+            Box<dyn FnMut(KeyCode, Duration) + Send + 'static>,
         >::new()));
 
+        let consumed = Arc::new(Mutex::new(HashSet::new()));
+        let thread_consumed = consumed.clone();
+
+        let pressed = Arc::new(Mutex::new(HashSet::new()));
+        let thread_pressed = pressed.clone();
+
         let (initialized_tx, initialized_rx) = channel();
         let (events_tx, events_rx) = channel();
 
@@ -115,13 +142,17 @@ impl Hook {
                         .map_err(|_| Error::ThreadStopped)?;
                 } else {
                     initialized_tx
-                        .send(Err(Error::WindowsHook))
+                        .send(Err(Error::BackendUnavailable {
+                            reason: "Couldn't install the low-level keyboard hook.",
+                        }))
                         .map_err(|_| Error::ThreadStopped)?;
                 }
 
                 *state.borrow_mut() = Some(State {
                     hook,
                     events: events_tx,
+                    consumed: thread_consumed,
+                    pressed: thread_pressed,
                 });
 
                 Ok(())
@@ -134,7 +165,7 @@ impl Hook {
                 if msg.message == MSG_EXIT {
                     break;
                 } else if ret < 0 {
-                    return Err(Error::MessageLoop);
+                    return Err(Error::ThreadStopped);
                 }
             }
 
@@ -148,24 +179,50 @@ impl Hook {
         let hotkey_map = hotkeys.clone();
 
         thread::spawn(move || {
-            while let Ok(key) = events_rx.recv() {
+            while let Ok((key, pressed_at)) = events_rx.recv() {
                 if let Some(callback) = hotkey_map.lock().get_mut(&key) {
-                    callback();
+                    callback(key, pressed_at.elapsed());
                 }
             }
         });
 
         let thread_id = initialized_rx.recv().map_err(|_| Error::ThreadStopped)??;
 
-        Ok(Hook { thread_id, hotkeys })
+        Ok(Hook {
+            thread_id,
+            hotkeys,
+            consumed,
+            pressed,
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        Backend::Windows
     }
 
     pub fn register<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
     where
-        F: FnMut() + Send + 'static,
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, false, callback)
+    }
+
+    pub fn register_consuming<F>(&self, hotkey: KeyCode, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
+    {
+        self.register_with(hotkey, true, callback)
+    }
+
+    fn register_with<F>(&self, hotkey: KeyCode, consume: bool, callback: F) -> Result<()>
+    where
+        F: FnMut(KeyCode, Duration) + Send + 'static,
     {
         if let Entry::Vacant(vacant) = self.hotkeys.lock().entry(hotkey) {
             vacant.insert(Box::new(callback));
+            if consume {
+                self.consumed.lock().insert(hotkey);
+            }
             Ok(())
         } else {
             Err(Error::AlreadyRegistered)
@@ -174,6 +231,44 @@ impl Hook {
 
     pub fn unregister(&self, hotkey: KeyCode) -> Result<()> {
         if self.hotkeys.lock().remove(&hotkey).is_some() {
+            self.consumed.lock().remove(&hotkey);
+            Ok(())
+        } else {
+            Err(Error::NotRegistered)
+        }
+    }
+
+    pub fn rebind(&self, old: KeyCode, new: KeyCode) -> Result<()> {
+        let mut hotkeys = self.hotkeys.lock();
+        if !hotkeys.contains_key(&old) {
+            return Err(Error::NotRegistered);
+        }
+        if old == new {
+            return Ok(());
+        }
+        if hotkeys.contains_key(&new) {
+            return Err(Error::AlreadyRegistered);
+        }
+        let callback = hotkeys.remove(&old).unwrap();
+        hotkeys.insert(new, callback);
+        let mut consumed = self.consumed.lock();
+        if consumed.remove(&old) {
+            consumed.insert(new);
+        }
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, hotkey: KeyCode) -> bool {
+        if self.consumed.lock().contains(&hotkey) {
+            return self.pressed.lock().contains(&hotkey);
+        }
+        // The most significant bit is set while the key is held down.
+        unsafe { GetAsyncKeyState(hotkey as c_int) < 0 }
+    }
+
+    pub fn trigger(&self, hotkey: KeyCode) -> Result<()> {
+        if let Some(callback) = self.hotkeys.lock().get_mut(&hotkey) {
+            callback(hotkey, Duration::default());
             Ok(())
         } else {
             Err(Error::NotRegistered)
@@ -184,9 +279,22 @@ impl Hook {
 #[test]
 fn test() {
     let hook = Hook::new().unwrap();
-    hook.register(KeyCode::Numpad0, || println!("A")).unwrap();
-    thread::sleep(std::time::Duration::from_secs(5));
+    hook.register(KeyCode::Numpad0, |_, _| println!("A"))
+        .unwrap();
+    thread::sleep(Duration::from_secs(5));
     hook.unregister(KeyCode::Numpad0).unwrap();
-    hook.register(KeyCode::Numpad1, || println!("B")).unwrap();
-    thread::sleep(std::time::Duration::from_secs(5));
+    hook.register(KeyCode::Numpad1, |_, _| println!("B"))
+        .unwrap();
+    thread::sleep(Duration::from_secs(5));
+}
+
+#[test]
+fn trigger() {
+    let hook = Hook::new().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    hook.register(KeyCode::Numpad1, move |key, _| sender.send(key).unwrap())
+        .unwrap();
+    hook.trigger(KeyCode::Numpad1).unwrap();
+    assert_eq!(receiver.try_recv(), Ok(KeyCode::Numpad1));
+    assert_eq!(hook.trigger(KeyCode::Numpad2), Err(Error::NotRegistered));
 }
@@ -2,8 +2,9 @@ use crate::{
     hotkey::{Hook, KeyCode},
     HotkeyConfig, SharedTimer,
 };
+use core::time::Duration;
 
-pub use crate::hotkey::{Error, Result};
+pub use crate::hotkey::{Backend, Error, Result};
 
 // This enum might be better situated in hotkey_config, but the last method should stay in this file
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -30,6 +31,18 @@ enum Hotkey {
 }
 
 impl Hotkey {
+    const ALL: [Hotkey; 9] = [
+        Hotkey::Split,
+        Hotkey::Reset,
+        Hotkey::Undo,
+        Hotkey::Skip,
+        Hotkey::Pause,
+        Hotkey::UndoAllPauses,
+        Hotkey::PreviousComparison,
+        Hotkey::NextComparison,
+        Hotkey::ToggleTimingMethod,
+    ];
+
     fn set_keycode(self, config: &mut HotkeyConfig, keycode: Option<KeyCode>) {
         match self {
             Hotkey::Split => config.split = keycode,
@@ -44,6 +57,15 @@ impl Hotkey {
         }
     }
 
+    /// Returns another hotkey that already uses the key in the configuration.
+    fn find_conflict(self, config: &HotkeyConfig, keycode: Option<KeyCode>) -> Option<Hotkey> {
+        keycode?;
+        Hotkey::ALL
+            .iter()
+            .copied()
+            .find(|&other| other != self && other.get_keycode(config) == keycode)
+    }
+
     const fn get_keycode(self, config: &HotkeyConfig) -> Option<KeyCode> {
         match self {
             Hotkey::Split => config.split,
@@ -58,19 +80,25 @@ impl Hotkey {
         }
     }
 
-    fn callback(self, timer: SharedTimer) -> Box<dyn FnMut() + Send + 'static> {
+    fn callback(self, timer: SharedTimer) -> Box<dyn FnMut(KeyCode, Duration) + Send + 'static> {
         match self {
-            Hotkey::Split => Box::new(move || timer.write().split_or_start()),
-            Hotkey::Reset => Box::new(move || timer.write().reset(true)),
-            Hotkey::Undo => Box::new(move || timer.write().undo_split()),
-            Hotkey::Skip => Box::new(move || timer.write().skip_split()),
-            Hotkey::Pause => Box::new(move || timer.write().toggle_pause_or_start()),
-            Hotkey::UndoAllPauses => Box::new(move || timer.write().undo_all_pauses()),
+            Hotkey::Split => {
+                Box::new(move |_, latency| timer.write().split_or_start_with_delay(latency))
+            }
+            Hotkey::Reset => Box::new(move |_, _| timer.write().reset(true)),
+            Hotkey::Undo => Box::new(move |_, _| timer.write().undo_split()),
+            Hotkey::Skip => Box::new(move |_, _| timer.write().skip_split()),
+            Hotkey::Pause => Box::new(move |_, _| timer.write().toggle_pause_or_start()),
+            Hotkey::UndoAllPauses => Box::new(move |_, _| timer.write().undo_all_pauses()),
             Hotkey::PreviousComparison => {
-                Box::new(move || timer.write().switch_to_previous_comparison())
+                Box::new(move |_, _| timer.write().switch_to_previous_comparison())
+            }
+            Hotkey::NextComparison => {
+                Box::new(move |_, _| timer.write().switch_to_next_comparison())
+            }
+            Hotkey::ToggleTimingMethod => {
+                Box::new(move |_, _| timer.write().toggle_timing_method())
             }
-            Hotkey::NextComparison => Box::new(move || timer.write().switch_to_next_comparison()),
-            Hotkey::ToggleTimingMethod => Box::new(move || timer.write().toggle_timing_method()),
         }
     }
 }
@@ -116,11 +144,6 @@ impl HotkeySystem {
         Ok(())
     }
 
-    fn register(&mut self, hotkey: Hotkey, keycode: Option<KeyCode>) -> Result<()> {
-        hotkey.set_keycode(&mut self.config, keycode);
-        unsafe { self.register_raw(hotkey) }
-    }
-
     // This method should never be public, because it might mess up the internal state and we might
     // leak a registered hotkey
     unsafe fn unregister_raw(&mut self, hotkey: Hotkey) -> Result<()> {
@@ -130,23 +153,39 @@ impl HotkeySystem {
         Ok(())
     }
 
-    fn unregister(&mut self, hotkey: Hotkey) -> Result<()> {
-        hotkey.set_keycode(&mut self.config, None);
-        unsafe { self.unregister_raw(hotkey) }
-    }
-
     fn set_hotkey(&mut self, hotkey: Hotkey, keycode: Option<KeyCode>) -> Result<()> {
-        // FixMe: We do not check whether the keycode is already in use
-        if hotkey.get_keycode(&self.config) == keycode {
+        let previous = hotkey.get_keycode(&self.config);
+        if previous == keycode {
             return Ok(());
         }
+        // The hook only knows about the keys while the Hotkey System is
+        // active, and not every backend rejects duplicate keys, so we check
+        // the configuration ourselves.
+        if self.checks_conflicts() && hotkey.find_conflict(&self.config, keycode).is_some() {
+            return Err(Error::AlreadyRegistered);
+        }
         if self.is_active {
-            self.unregister(hotkey)?;
-            self.register(hotkey, keycode)?;
+            match (previous, keycode) {
+                // Rebinding moves the callback over in one step, so there's no
+                // moment where neither or both of the keys trigger the action.
+                (Some(previous), Some(keycode)) => self.hook.rebind(previous, keycode)?,
+                (Some(previous), None) => self.hook.unregister(previous)?,
+                (None, Some(keycode)) => self
+                    .hook
+                    .register(keycode, hotkey.callback(self.timer.clone()))?,
+                (None, None) => {}
+            }
         }
+        hotkey.set_keycode(&mut self.config, keycode);
         Ok(())
     }
 
+    fn checks_conflicts(&self) -> bool {
+        // All the keys are the same on platforms without hotkey support, so
+        // there's nothing to check there.
+        self.hook.backend() != Backend::Unsupported
+    }
+
     /// Sets the key to use for splitting and starting a new attempt.
     pub fn set_split(&mut self, hotkey: Option<KeyCode>) -> Result<()> {
         self.set_hotkey(Hotkey::Split, hotkey)
@@ -240,6 +279,11 @@ impl HotkeySystem {
         self.is_active
     }
 
+    /// Returns the backend the Hotkey System uses to listen for the hotkeys.
+    pub fn backend(&self) -> Backend {
+        self.hook.backend()
+    }
+
     /// Returns the hotkey configuration currently in use by the Hotkey System.
     pub const fn config(&self) -> HotkeyConfig {
         self.config
@@ -248,18 +292,131 @@ impl HotkeySystem {
     /// Applies a new hotkey configuration to the Hotkey System. Each hotkey is
     /// changed to the one specified in the configuration. This operation may
     /// fail if you provide a hotkey configuration where a hotkey is used for
-    /// multiple operations.
+    /// multiple operations. The configuration stays unchanged in that case.
     pub fn set_config(&mut self, config: HotkeyConfig) -> Result<()> {
-        self.set_split(config.split)?;
-        self.set_reset(config.reset)?;
-        self.set_undo(config.undo)?;
-        self.set_skip(config.skip)?;
-        self.set_pause(config.pause)?;
-        self.set_previous_comparison(config.previous_comparison)?;
-        self.set_next_comparison(config.next_comparison)?;
-        self.set_undo_all_pauses(config.undo_all_pauses)?;
-        self.set_toggle_timing_method(config.toggle_timing_method)?;
+        if self.checks_conflicts() && has_conflicts(&config) {
+            return Err(Error::AlreadyRegistered);
+        }
+
+        let previous = self.config;
+        let changed = Hotkey::ALL
+            .iter()
+            .copied()
+            .filter(|hotkey| hotkey.get_keycode(&previous) != hotkey.get_keycode(&config));
+
+        if self.is_active {
+            // All the changed keys get unregistered before any of the new ones
+            // get registered, so keys can be swapped between the actions.
+            for hotkey in changed.clone() {
+                unsafe { self.unregister_raw(hotkey)? };
+            }
+            self.config = config;
+            for (registered, hotkey) in changed.clone().enumerate() {
+                if let Err(e) = unsafe { self.register_raw(hotkey) } {
+                    // Restore the previous keys, so the hooks match the
+                    // unchanged configuration again.
+                    for hotkey in changed.clone().take(registered) {
+                        unsafe { self.unregister_raw(hotkey).ok() };
+                    }
+                    self.config = previous;
+                    for hotkey in changed {
+                        unsafe { self.register_raw(hotkey).ok() };
+                    }
+                    return Err(e);
+                }
+            }
+        } else {
+            self.config = config;
+        }
 
         Ok(())
     }
 }
+
+fn has_conflicts(config: &HotkeyConfig) -> bool {
+    Hotkey::ALL.iter().any(|&hotkey| {
+        hotkey
+            .find_conflict(config, hotkey.get_keycode(config))
+            .is_some()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Run, Segment, Timer};
+
+    fn config() -> HotkeyConfig {
+        HotkeyConfig {
+            undo: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn binding_a_key_of_another_action_conflicts() {
+        let config = config();
+
+        // Registering the key for an unbound action.
+        assert_eq!(
+            Hotkey::Undo.find_conflict(&config, config.split),
+            Some(Hotkey::Split)
+        );
+        // Rebinding a bound action to the key.
+        assert_eq!(
+            Hotkey::Reset.find_conflict(&config, config.split),
+            Some(Hotkey::Split)
+        );
+        // Keeping the key or unbinding the action.
+        assert_eq!(Hotkey::Split.find_conflict(&config, config.split), None);
+        assert_eq!(Hotkey::Split.find_conflict(&config, None), None);
+        // A key no action uses.
+        assert_eq!(Hotkey::Reset.find_conflict(&config, config.undo), None);
+    }
+
+    #[test]
+    fn swapping_keys_has_no_conflicts() {
+        let mut config = config();
+        assert!(!has_conflicts(&config));
+
+        config.split = HotkeyConfig::default().reset;
+        config.reset = HotkeyConfig::default().split;
+        assert!(!has_conflicts(&config));
+
+        config.reset = config.split;
+        assert!(has_conflicts(&config));
+    }
+
+    fn timer() -> SharedTimer {
+        let mut run = Run::new();
+        run.push_segment(Segment::new("A"));
+        Timer::new(run).unwrap().into_shared()
+    }
+
+    #[test]
+    #[ignore = "needs a hotkey backend"]
+    fn set_config_swaps_keys() {
+        let mut hotkey_system = HotkeySystem::new(timer()).unwrap();
+
+        let mut config = hotkey_system.config();
+        config.split = HotkeyConfig::default().reset;
+        config.reset = HotkeyConfig::default().split;
+
+        hotkey_system.set_config(config).unwrap();
+        assert_eq!(hotkey_system.config(), config);
+
+        let mut duplicates = config;
+        duplicates.undo = config.split;
+        assert_eq!(
+            hotkey_system.set_config(duplicates),
+            Err(Error::AlreadyRegistered)
+        );
+        assert_eq!(hotkey_system.config(), config);
+
+        hotkey_system.deactivate().unwrap();
+        config.split = HotkeyConfig::default().split;
+        config.reset = HotkeyConfig::default().reset;
+        hotkey_system.set_config(config).unwrap();
+        assert_eq!(hotkey_system.config(), config);
+    }
+}
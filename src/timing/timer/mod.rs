@@ -2,7 +2,7 @@ use crate::{
     comparison::personal_best, platform::prelude::*, AtomicDateTime, Run, Segment, Time, TimeSpan,
     TimeStamp, TimerPhase, TimerPhase::*, TimingMethod,
 };
-use core::{mem, ops::Deref};
+use core::{mem, ops::Deref, time::Duration};
 
 #[cfg(test)]
 mod tests;
@@ -192,9 +192,13 @@ impl Timer {
     }
 
     fn current_time(&self) -> Time {
+        self.time_at(TimeStamp::now())
+    }
+
+    fn time_at(&self, now: TimeStamp) -> Time {
         let real_time = match self.phase {
             NotRunning => Some(self.run.offset()),
-            Running => Some(TimeStamp::now() - self.adjusted_start_time),
+            Running => Some(now - self.adjusted_start_time),
             Paused => Some(self.time_paused_at),
             Ended => self.run.segments().last().unwrap().split_time().real_time,
         };
@@ -296,11 +300,15 @@ impl Timer {
     /// Starts the Timer if there is no attempt in progress. If that's not the
     /// case, nothing happens.
     pub fn start(&mut self) {
+        self.start_at(TimeStamp::now());
+    }
+
+    fn start_at(&mut self, now: TimeStamp) {
         if self.phase == NotRunning {
             self.phase = Running;
             self.current_split_index = Some(0);
             self.attempt_started = Some(AtomicDateTime::now());
-            self.start_time = TimeStamp::now();
+            self.start_time = now;
             self.start_time_with_offset = self.start_time - self.run.offset();
             self.adjusted_start_time = self.start_time_with_offset;
             self.time_paused_at = self.run.offset();
@@ -314,7 +322,11 @@ impl Timer {
     /// If an attempt is in progress, stores the current time as the time of the
     /// current split. The attempt ends if the last split time is stored.
     pub fn split(&mut self) {
-        let current_time = self.current_time();
+        self.split_at(TimeStamp::now());
+    }
+
+    fn split_at(&mut self, now: TimeStamp) {
+        let current_time = self.time_at(now);
         if self.phase == Running
             && current_time
                 .real_time
@@ -337,13 +349,44 @@ impl Timer {
     /// Starts a new attempt or stores the current time as the time of the
     /// current split. The attempt ends if the last split time is stored.
     pub fn split_or_start(&mut self) {
-        if self.phase == NotRunning {
-            self.start();
-        } else {
-            self.split();
+        self.split_or_start_with_delay(Duration::default());
+    }
+
+    /// Starts a new attempt or stores the current time as the time of the
+    /// current split, as if this happened the given amount of time ago. This
+    /// allows compensating for the delay between the key press and the hotkey
+    /// being handled. A split is never moved before the previous split or
+    /// before the attempt got resumed. Pausing and resuming can't be delayed
+    /// like this, so any delay of those still affects all the later split
+    /// times. The attempt ends if the last split time is stored.
+    pub fn split_or_start_with_delay(&mut self, delay: Duration) {
+        let now = TimeStamp::now();
+        let delay = TimeSpan::from(delay);
+        match self.phase {
+            NotRunning => self.start_at(now - delay),
+            Running => {
+                let available = now - self.adjusted_start_time - self.earliest_split_time();
+                let delay = delay.min(available.max(TimeSpan::zero()));
+                self.split_at(now - delay);
+            }
+            Paused | Ended => {}
         }
     }
 
+    fn earliest_split_time(&self) -> TimeSpan {
+        // The time the attempt got resumed at is the time it got paused at,
+        // which is the offset if it never got paused.
+        let previous_split = self.current_split_index.and_then(|index| {
+            self.run.segments()[..index]
+                .iter()
+                .rev()
+                .find_map(|segment| segment.split_time().real_time)
+        });
+        previous_split.map_or(self.time_paused_at, |split_time| {
+            split_time.max(self.time_paused_at)
+        })
+    }
+
     /// Skips the current split if an attempt is in progress and the
     /// current split is not the last split.
     pub fn skip_split(&mut self) {
@@ -3,6 +3,7 @@ use crate::{
     tests_helper::{run_with_splits, run_with_splits_opt, start_run},
     Run, Segment, TimeSpan, Timer, TimerPhase, TimingMethod,
 };
+use core::time::Duration;
 
 mod mark_as_modified;
 mod variables;
@@ -619,3 +620,47 @@ fn reset_and_set_attempt_as_pb() {
     assert_eq!(attempt.time().game_time, None);
     assert!(attempt.ended().unwrap().time >= attempt.started().unwrap().time);
 }
+
+#[test]
+fn split_or_start_with_delay_backdates_the_times() {
+    let mut timer = timer();
+
+    timer.split_or_start_with_delay(Duration::from_secs(10));
+    let current_time = timer.snapshot().current_time().real_time.unwrap();
+    assert!(current_time >= TimeSpan::from_seconds(10.0));
+    assert!(current_time < TimeSpan::from_seconds(11.0));
+
+    timer.split_or_start_with_delay(Duration::from_secs(4));
+    let split_time = timer.run().segment(0).split_time().real_time.unwrap();
+    assert!(split_time >= TimeSpan::from_seconds(6.0));
+    assert!(split_time < TimeSpan::from_seconds(7.0));
+}
+
+#[test]
+fn split_or_start_with_delay_doesnt_go_before_the_previous_split() {
+    let mut timer = timer();
+
+    timer.split_or_start_with_delay(Duration::from_secs(10));
+    timer.split();
+    timer.split_or_start_with_delay(Duration::from_secs(5));
+
+    let first = timer.run().segment(0).split_time().real_time.unwrap();
+    let second = timer.run().segment(1).split_time().real_time.unwrap();
+    assert!(first >= TimeSpan::from_seconds(10.0));
+    assert!(second >= first);
+    assert!(second < TimeSpan::from_seconds(11.0));
+}
+
+#[test]
+fn split_or_start_with_delay_doesnt_go_before_resuming() {
+    let mut timer = timer();
+
+    timer.split_or_start_with_delay(Duration::from_secs(10));
+    timer.pause();
+    timer.resume();
+    timer.split_or_start_with_delay(Duration::from_secs(5));
+
+    let split_time = timer.run().segment(0).split_time().real_time.unwrap();
+    assert!(split_time >= TimeSpan::from_seconds(10.0));
+    assert!(split_time < TimeSpan::from_seconds(11.0));
+}